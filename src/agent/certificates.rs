@@ -0,0 +1,150 @@
+use std::fmt::Display;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::format_duration;
+
+use super::protocol::Identity;
+use super::Agent;
+
+/// The details of a certificate identity held by an agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    pub identity: Identity,
+    pub key_id: String,
+    pub principals: Vec<String>,
+    /// The start of the validity window, in seconds since the Unix epoch.
+    pub valid_after: u64,
+    /// The end of the validity window, in seconds since the Unix epoch.
+    pub valid_before: u64,
+}
+
+impl CertificateInfo {
+    /// Read the certificate details of `identity`, if it is a certificate.
+    pub fn from_identity(identity: &Identity) -> Option<Self> {
+        let certificate = identity.certificate()?;
+        Some(Self {
+            identity: identity.clone(),
+            key_id: certificate.key_id().to_string(),
+            principals: certificate.valid_principals().to_vec(),
+            valid_after: certificate.valid_after(),
+            valid_before: certificate.valid_before(),
+        })
+    }
+
+    /// Whether the validity window has ended.
+    pub fn is_expired(&self) -> bool {
+        now() >= self.valid_before
+    }
+
+    /// How long until the validity window ends, or `None` if it already has.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.valid_before
+            .checked_sub(now())
+            .filter(|r| *r > 0)
+            .map(Duration::from_secs)
+    }
+}
+
+impl Display for CertificateInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let principals = if self.principals.is_empty() {
+            "any principal".to_string()
+        } else {
+            self.principals.join(", ")
+        };
+        let remaining = match self.remaining() {
+            _ if self.valid_before == u64::MAX => "never expires".to_string(),
+            Some(remaining) => format!("{} left", format_duration(remaining)),
+            None => "EXPIRED".to_string(),
+        };
+        let window = match (self.valid_after, self.valid_before) {
+            (0, u64::MAX) => "forever".to_string(),
+            (0, before) => format!("until {}", format_timestamp(before)),
+            (after, u64::MAX) => format!("from {}", format_timestamp(after)),
+            (after, before) => format!(
+                "from {} until {}",
+                format_timestamp(after),
+                format_timestamp(before)
+            ),
+        };
+        write!(
+            f,
+            "{} ({}) for {}, valid {}, {}",
+            self.key_id,
+            self.identity.fingerprint(),
+            principals,
+            window,
+            remaining
+        )
+    }
+}
+
+impl Agent {
+    /// List the certificate identities the agent holds.
+    pub fn certificates(&self) -> io::Result<Vec<CertificateInfo>> {
+        Ok(self
+            .identities()?
+            .iter()
+            .filter_map(CertificateInfo::from_identity)
+            .collect())
+    }
+
+    /// The number of expired certificates the agent holds, or 0 if it can't list its identities.
+    pub fn expired_certificate_count(&self) -> usize {
+        self.certificates()
+            .map(|certs| certs.iter().filter(|c| c.is_expired()).count())
+            .unwrap_or_default()
+    }
+
+    /// Remove the expired certificates from the agent, leaving the plain keys they certify,
+    /// returning the ones removed.
+    pub fn remove_expired_certificates(&self) -> io::Result<Vec<CertificateInfo>> {
+        let expired: Vec<CertificateInfo> = self
+            .certificates()?
+            .into_iter()
+            .filter(CertificateInfo::is_expired)
+            .collect();
+        let mut client = self.client()?;
+        for certificate in &expired {
+            client.remove_identity(&certificate.identity.key_blob)?;
+        }
+        Ok(expired)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Format seconds since the Unix epoch as a UTC date and time, like `2024-05-01 13:00 UTC`.
+fn format_timestamp(timestamp: u64) -> String {
+    // civil-from-days, from Howard Hinnant's date algorithms
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let seconds_of_day = timestamp % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60
+    )
+}
@@ -0,0 +1,99 @@
+use std::env;
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use super::Agent;
+
+/// The possible states of the agent the calling shell uses through `SSH_AUTH_SOCK` and
+/// `SSH_AGENT_PID`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CurrentAgentStatus {
+    /// `SSH_AUTH_SOCK` isn't set.
+    Unset,
+    /// `SSH_AUTH_SOCK` points at one of the running agents.
+    Valid(Agent),
+    /// `SSH_AUTH_SOCK` points at one of the running agents, but `SSH_AGENT_PID` names a different
+    /// process.
+    PidMismatch { agent: Agent, env_pid: String },
+    /// `SSH_AUTH_SOCK` points at a live socket that wasn't discovered, like a forwarded agent.
+    Unmanaged(PathBuf),
+    /// `SSH_AUTH_SOCK` points at a socket that nothing is listening on.
+    DeadSocket(PathBuf),
+}
+
+impl CurrentAgentStatus {
+    /// The running agent the calling shell uses, if it is one of the discovered agents.
+    pub fn agent(&self) -> Option<&Agent> {
+        match self {
+            CurrentAgentStatus::Valid(agent) | CurrentAgentStatus::PidMismatch { agent, .. } => {
+                Some(agent)
+            }
+            _ => None,
+        }
+    }
+
+    /// A warning to show the user if the environment doesn't match the running agents.
+    pub fn warning(&self) -> Option<String> {
+        match self {
+            CurrentAgentStatus::PidMismatch { agent, env_pid } => Some(format!(
+                "SSH_AGENT_PID is {} but the agent at {} has PID {}",
+                env_pid,
+                agent.socket_path.display(),
+                agent.pid
+            )),
+            CurrentAgentStatus::DeadSocket(path) => Some(format!(
+                "SSH_AUTH_SOCK points at {}, which is not a running agent",
+                path.display()
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `path` is the socket in the caller's `SSH_AUTH_SOCK`.
+pub fn is_current_socket(path: &Path) -> bool {
+    env::var_os("SSH_AUTH_SOCK").is_some_and(|sock| is_same_socket(Path::new(&sock), path))
+}
+
+/// Whether `sock` is the agent socket at `path`, directly or through symlinks like the stable
+/// socket.
+fn is_same_socket(sock: &Path, path: &Path) -> bool {
+    if sock == path || fs::read_link(sock).is_ok_and(|target| target == path) {
+        return true;
+    }
+    match (sock.canonicalize(), path.canonicalize()) {
+        (Ok(sock), Ok(path)) => sock == path,
+        _ => false,
+    }
+}
+
+/// Check the caller's `SSH_AUTH_SOCK` and `SSH_AGENT_PID` against the `running_agents`.
+pub fn current_agent_status(running_agents: &[Agent]) -> CurrentAgentStatus {
+    let Some(sock) = env::var_os("SSH_AUTH_SOCK").filter(|s| !s.is_empty()) else {
+        return CurrentAgentStatus::Unset;
+    };
+    let sock = PathBuf::from(sock);
+
+    match running_agents
+        .iter()
+        .find(|a| is_same_socket(&sock, &a.socket_path))
+    {
+        Some(agent) => match env::var("SSH_AGENT_PID") {
+            Ok(env_pid) if !env_pid.is_empty() && env_pid != agent.pid => {
+                CurrentAgentStatus::PidMismatch {
+                    agent: agent.clone(),
+                    env_pid,
+                }
+            }
+            _ => CurrentAgentStatus::Valid(agent.clone()),
+        },
+        None => {
+            if UnixStream::connect(&sock).is_ok() {
+                CurrentAgentStatus::Unmanaged(sock)
+            } else {
+                CurrentAgentStatus::DeadSocket(sock)
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use super::Agent;
+
+/// An identity and the PIDs of the compared agents holding it.
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
+    pub fingerprint: String,
+    pub comment: String,
+    pub holders: Vec<String>,
+}
+
+/// The identities of several agents, split into the ones common to two or more agents and the
+/// ones unique to each agent.
+#[derive(Debug, Clone)]
+pub struct IdentityDiff {
+    pub common: Vec<DiffEntry>,
+    pub unique: Vec<(Agent, Vec<DiffEntry>)>,
+}
+
+/// Compare the identities of `agents`.
+///
+/// Agents that can't list their identities, like locked ones, are compared as holding none.
+pub fn diff_identities(agents: &[Agent]) -> IdentityDiff {
+    let mut entries: BTreeMap<String, DiffEntry> = BTreeMap::new();
+    for agent in agents {
+        for identity in agent.identities().unwrap_or_default() {
+            let fingerprint = identity.fingerprint();
+            let entry = entries
+                .entry(fingerprint.clone())
+                .or_insert_with(|| DiffEntry {
+                    fingerprint,
+                    comment: identity.comment.clone(),
+                    holders: vec![],
+                });
+            // a key and its certificate share a fingerprint, but the agent only holds it once
+            if !entry.holders.contains(&agent.pid) {
+                entry.holders.push(agent.pid.clone());
+            }
+        }
+    }
+
+    let (common, unique): (Vec<DiffEntry>, Vec<DiffEntry>) =
+        entries.into_values().partition(|e| e.holders.len() > 1);
+    let unique = agents
+        .iter()
+        .map(|agent| {
+            let own = unique
+                .iter()
+                .filter(|e| e.holders[0] == agent.pid)
+                .cloned()
+                .collect();
+            (agent.clone(), own)
+        })
+        .collect();
+
+    IdentityDiff { common, unique }
+}
+
+impl Display for IdentityDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Common to two or more agents:")?;
+        if self.common.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for entry in &self.common {
+            writeln!(
+                f,
+                "  {} {} (PIDs {})",
+                entry.fingerprint,
+                entry.comment,
+                entry.holders.join(", ")
+            )?;
+        }
+
+        for (agent, entries) in &self.unique {
+            writeln!(
+                f,
+                "Only in PID {} at {}:",
+                agent.pid,
+                agent.socket_path.display()
+            )?;
+            if entries.is_empty() {
+                writeln!(f, "  (none)")?;
+            }
+            for entry in entries {
+                writeln!(f, "  {} {}", entry.fingerprint, entry.comment)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,22 @@
 use std::fs;
-use std::io::Result;
+use std::io::{ErrorKind, Result};
+use std::os::unix::net::UnixStream;
 
 use crate::agent::Agent;
+use crate::publish;
 
 impl Agent {
+    /// Whether nothing listens on the agent's socket anymore, so it is safe to remove.
+    pub fn is_abandoned(&self) -> bool {
+        matches!(
+            UnixStream::connect(&self.socket_path),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused
+        )
+    }
+
     pub fn clean_dead_agent_socket(&self) -> Result<()> {
         if self.is_running {
-            println!(
+            eprintln!(
                 "{} at {} is running and the socket can't be removed",
                 &self,
                 &self.socket_path.display()
@@ -17,14 +27,22 @@ impl Agent {
                 "ssh-agent running",
             ));
         }
+        if !self.is_abandoned() {
+            return Err(std::io::Error::new(
+                ErrorKind::AddrInUse,
+                "something is still listening on the socket",
+            ));
+        }
 
         fs::remove_file(&self.socket_path)?;
-        let socket_dir = if let Some(dir) = &self.socket_path.parent() {
-            dir
-        } else {
-            self.socket_path.as_path()
-        };
-        fs::remove_dir(socket_dir)?;
+        publish::retract(self);
+        // only remove directories in the style `ssh-agent` creates, never a discovery directory
+        if let Some(socket_dir) = self.socket_path.parent().filter(|dir| {
+            dir.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("ssh-"))
+        }) {
+            fs::remove_dir(socket_dir)?;
+        }
 
         Ok(())
     }
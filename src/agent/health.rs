@@ -0,0 +1,156 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::time::{Duration, Instant};
+
+use crate::config;
+
+use super::protocol::Identity;
+use super::Agent;
+
+/// How well an agent answers requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentHealth {
+    /// The agent answered in time.
+    Healthy,
+    /// The agent answered in time, but Agency locked it, so it lists no identities.
+    Locked,
+    /// The agent answered, but took longer than the configured slow threshold.
+    Slow(Duration),
+    /// The agent accepted the connection but didn't answer within the configured timeout.
+    Hung,
+    /// Nothing is listening on the socket.
+    Refused,
+    /// The socket path isn't a socket.
+    NotASocket,
+    /// The socket belongs to someone else.
+    PermissionDenied,
+}
+
+impl Display for AgentHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentHealth::Healthy => write!(f, "healthy"),
+            AgentHealth::Locked => write!(f, "locked"),
+            AgentHealth::Slow(took) => write!(f, "slow, {}ms", took.as_millis()),
+            AgentHealth::Hung => write!(f, "hung"),
+            AgentHealth::Refused => write!(f, "refused"),
+            AgentHealth::NotASocket => write!(f, "not a socket"),
+            AgentHealth::PermissionDenied => write!(f, "permission denied"),
+        }
+    }
+}
+
+impl AgentHealth {
+    /// Whether the agent answers requests at all.
+    pub fn is_responsive(&self) -> bool {
+        matches!(
+            self,
+            AgentHealth::Healthy | AgentHealth::Locked | AgentHealth::Slow(_)
+        )
+    }
+}
+
+/// What probing an agent found.
+///
+/// Probes are taken once per run by `probe_agents` and cached on the agent, so listings, sorting
+/// and the other status checks don't each go back to the agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentProbe {
+    pub health: AgentHealth,
+    /// The identities the agent listed, or `None` if it couldn't be asked.
+    pub identities: Option<Vec<Identity>>,
+}
+
+impl AgentProbe {
+    fn unreachable(health: AgentHealth) -> Self {
+        Self {
+            health,
+            identities: None,
+        }
+    }
+
+    /// The identities the agent listed, or why it couldn't be asked.
+    pub fn identities(&self) -> io::Result<Vec<Identity>> {
+        match (&self.identities, self.health) {
+            (Some(identities), _) => Ok(identities.clone()),
+            (None, AgentHealth::Hung) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "agent did not answer in time",
+            )),
+            (None, AgentHealth::PermissionDenied) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "permission denied",
+            )),
+            (None, _) => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "agent refused the connection",
+            )),
+        }
+    }
+}
+
+impl Agent {
+    /// The cached probe of the agent, or a fresh one if it hasn't been probed.
+    pub fn probe(&self) -> Cow<'_, AgentProbe> {
+        match &self.probe {
+            Some(probe) => Cow::Borrowed(probe),
+            None => Cow::Owned(self.probe_now()),
+        }
+    }
+
+    /// How well the agent answers requests, from the cached probe if there is one.
+    pub fn probe_health(&self) -> AgentHealth {
+        self.probe().health
+    }
+
+    /// Probe how well the agent answers an identity request, waiting at most the configured
+    /// timeout, regardless of any cached probe.
+    pub fn probe_now(&self) -> AgentProbe {
+        match fs::metadata(&self.socket_path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                return AgentProbe::unreachable(AgentHealth::NotASocket)
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                return AgentProbe::unreachable(AgentHealth::PermissionDenied)
+            }
+            Err(_) => return AgentProbe::unreachable(AgentHealth::Refused),
+        }
+
+        let unreachable = |e: io::Error| {
+            AgentProbe::unreachable(match e.kind() {
+                io::ErrorKind::PermissionDenied => AgentHealth::PermissionDenied,
+                io::ErrorKind::TimedOut => AgentHealth::Hung,
+                _ => AgentHealth::Refused,
+            })
+        };
+        let mut client = match self.client() {
+            Ok(client) => client,
+            Err(e) => return unreachable(e),
+        };
+
+        let start = Instant::now();
+        let identities = match client.request_identities() {
+            Ok(identities) => identities,
+            Err(e) => return unreachable(e),
+        };
+        let took = start.elapsed();
+
+        let health = if took > config::get().health.slow() {
+            AgentHealth::Slow(took)
+        } else if identities.is_empty() && self.is_locked_by_agency() {
+            // only the locks Agency made are known, since asking an agent whether it is locked
+            // changes it
+            AgentHealth::Locked
+        } else {
+            AgentHealth::Healthy
+        };
+        AgentProbe {
+            health,
+            identities: Some(identities),
+        }
+    }
+}
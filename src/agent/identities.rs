@@ -1,14 +1,32 @@
+use std::env;
 use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use inquire::Password;
+use signature::Verifier;
+use ssh_key::{HashAlg, PrivateKey, PublicKey};
+
+use crate::config;
+
+use super::idle::set_access_time;
+use super::protocol::{AgentClient, Constraints, Identity};
+use super::Agent;
 
 /// The possible statuses of an agent's identity list.
 ///
 /// An agent that is not alive returns an error when the identity list is queried, which is
-/// represented here as `ConnectionRefused`.
+/// represented here as `ConnectionRefused`. A locked agent hides its identities, so one that Agency
+/// locked is reported as `Locked` rather than `NoIdentities`.
 #[derive(Debug, Default)]
 pub enum AgentIdentityStatus {
     #[default]
     NoIdentities,
     Identities(i32),
+    Locked,
     ConnectionRefused,
 }
 
@@ -26,9 +44,203 @@ impl Display for AgentIdentityStatus {
                     if n == &1 { "identity" } else { "identities" }
                 )
             }
+            AgentIdentityStatus::Locked => {
+                write!(f, "Locked")
+            }
             AgentIdentityStatus::ConnectionRefused => {
                 write!(f, "Connection attempt refused")
             }
         }
     }
 }
+
+impl Agent {
+    /// Connect to the agent's socket with the native protocol client.
+    ///
+    /// The socket's access time is put back afterwards, so Agency's own connections don't make
+    /// the agent look used.
+    pub fn client(&self) -> io::Result<AgentClient> {
+        let last_used = self.last_used();
+        let client = AgentClient::connect(&self.socket_path, config::get().health.timeout())?;
+        if let Some(last_used) = last_used {
+            let _ = set_access_time(&self.socket_path, last_used);
+        }
+        Ok(client)
+    }
+
+    /// The identities loaded in the agent, from the cached probe if there is one.
+    pub fn identities(&self) -> io::Result<Vec<Identity>> {
+        match &self.probe {
+            Some(probe) => probe.identities(),
+            None => self.client()?.request_identities(),
+        }
+    }
+
+    /// The SHA256 fingerprints of the identities loaded in the agent.
+    pub fn identity_fingerprints(&self) -> io::Result<Vec<String>> {
+        Ok(self
+            .identities()?
+            .iter()
+            .map(Identity::fingerprint)
+            .collect())
+    }
+
+    /// Add the private key at `path` to the agent, returning its fingerprint.
+    ///
+    /// Passphrases for encrypted keys are asked for through `$SSH_ASKPASS` when it is set, or on
+    /// the terminal otherwise.
+    pub fn add_identity_file(&self, path: &Path, constraints: Constraints) -> io::Result<String> {
+        let key = read_private_key(path)?;
+        self.client()?.add_identity(&key, constraints)?;
+        Ok(key.fingerprint(HashAlg::Sha256).to_string())
+    }
+
+    /// Remove every identity matching `fingerprint`, returning how many were removed.
+    ///
+    /// Certificates match the fingerprint of the key they certify.
+    pub fn remove_identities_by_fingerprint(&self, fingerprint: &str) -> io::Result<usize> {
+        let mut client = self.client()?;
+        let matching: Vec<Identity> = client
+            .request_identities()?
+            .into_iter()
+            .filter(|i| i.fingerprint() == fingerprint)
+            .collect();
+        for identity in &matching {
+            client.remove_identity(&identity.key_blob)?;
+        }
+        Ok(matching.len())
+    }
+
+    /// Remove every identity from the agent.
+    pub fn remove_all_identities(&self) -> io::Result<()> {
+        self.client()?.remove_all_identities()
+    }
+
+    /// Have the agent sign a random challenge with `identity` and verify the signature against
+    /// its public key, returning how long the agent took to sign.
+    pub fn verify_signing(&self, identity: &Identity) -> io::Result<Duration> {
+        let key_data = identity
+            .key_data()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported key type"))?;
+        let mut challenge = [0u8; 32];
+        File::open("/dev/urandom")?.read_exact(&mut challenge)?;
+
+        let mut client = self.client()?;
+        let start = Instant::now();
+        let signature = client.sign(identity, &challenge)?;
+        let elapsed = start.elapsed();
+
+        key_data
+            .verify(&challenge, &signature)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "signature does not verify"))?;
+        Ok(elapsed)
+    }
+
+    /// Add the identities from the configuration that the agent doesn't have yet.
+    ///
+    /// Identities are compared by fingerprint, so keys loaded some other way are skipped too.
+    /// Failures are reported on stderr without stopping the remaining keys from loading.
+    pub fn load_configured_identities(&self) {
+        let keys = &config::get().keys;
+        if keys.is_empty() {
+            return;
+        }
+
+        let loaded = self.identity_fingerprints().unwrap_or_default();
+        for key in keys {
+            let path = key.expanded_path();
+            if key_file_fingerprint(&path).is_some_and(|fp| loaded.contains(&fp)) {
+                continue;
+            }
+
+            let result = key.lifetime_duration().and_then(|lifetime| {
+                let constraints = Constraints {
+                    lifetime,
+                    confirm: key.confirm,
+                };
+                self.add_identity_file(&path, constraints)
+            });
+            match result {
+                Ok(fingerprint) => {
+                    eprintln!("Identity added: {} ({})", path.display(), fingerprint)
+                }
+                Err(e) => eprintln!("Unable to load {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+/// The SHA256 fingerprint of a public or private key file.
+///
+/// Private keys in the OpenSSH format store their public key unencrypted, so no passphrase is
+/// needed.
+pub fn key_file_fingerprint(path: &Path) -> Option<String> {
+    let fingerprint = match PublicKey::read_openssh_file(path) {
+        Ok(public_key) => public_key.fingerprint(HashAlg::Sha256),
+        Err(_) => PrivateKey::read_openssh_file(path)
+            .ok()?
+            .fingerprint(HashAlg::Sha256),
+    };
+    Some(fingerprint.to_string())
+}
+
+/// Read the OpenSSH private key at `path`, decrypting it if needed.
+fn read_private_key(path: &Path) -> io::Result<PrivateKey> {
+    let key = PrivateKey::read_openssh_file(path).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unable to read OpenSSH private key: {}", e),
+        )
+    })?;
+    if !key.is_encrypted() {
+        return Ok(key);
+    }
+
+    // give up after as many attempts as `ssh-add` allows
+    let prompt = format!("Enter passphrase for {}:", path.display());
+    for _ in 0..3 {
+        let passphrase = ask_passphrase(&prompt, false)?;
+        if let Ok(decrypted) = key.decrypt(passphrase.as_bytes()) {
+            return Ok(decrypted);
+        }
+        eprintln!("Bad passphrase, try again for {}", path.display());
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "too many incorrect passphrases",
+    ))
+}
+
+/// Ask the user for a passphrase through `$SSH_ASKPASS` if it is set, or on the terminal.
+///
+/// With `confirm`, a passphrase typed on the terminal must be entered twice.
+pub(crate) fn ask_passphrase(prompt: &str, confirm: bool) -> io::Result<String> {
+    let askpass = env::var_os("SSH_ASKPASS").filter(|p| !p.is_empty());
+    let askpass_allowed = env::var("SSH_ASKPASS_REQUIRE").map_or(true, |r| r != "never");
+    if let (Some(askpass), true) = (askpass, askpass_allowed) {
+        let output = Command::new(askpass)
+            .arg(prompt)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "passphrase prompt cancelled",
+            ));
+        }
+        let passphrase = String::from_utf8_lossy(&output.stdout);
+        return Ok(passphrase.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let password = Password::new(prompt);
+    let password = if confirm {
+        password
+    } else {
+        password.without_confirmation()
+    };
+    password
+        .prompt()
+        .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))
+}
@@ -0,0 +1,225 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::format_duration;
+
+use super::identities::AgentIdentityStatus;
+use super::Agent;
+
+/// What to do with an agent holding identities that hasn't been used for too long.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Lock the agent with a passphrase.
+    Lock,
+    /// Remove every identity from the agent.
+    #[default]
+    Clear,
+    /// Kill the agent.
+    Kill,
+}
+
+/// How far the access time of a file can lag behind its last use on a `relatime` mount: the
+/// kernel only updates access times older than a day, or not newer than the file's last change.
+const RELATIME_LAG: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl Agent {
+    /// When a client last connected to the agent, as far as the access time of its socket tells.
+    ///
+    /// Agency puts the access time back after its own connections, so they don't count. On most
+    /// mounts an access time newer than the socket's last change is only updated once a day, so
+    /// this can be up to a day earlier than the last use; `idle_time` accounts for that.
+    pub fn last_used(&self) -> Option<SystemTime> {
+        fs::symlink_metadata(&self.socket_path)
+            .and_then(|m| m.accessed())
+            .ok()
+    }
+
+    /// How long the agent has gone unused at least.
+    ///
+    /// The time the socket's access time can lag behind its last use on its mount is taken off,
+    /// so an agent in use never looks idle. There is no lag while the access time isn't newer than
+    /// the socket's last change, as after Agency puts it back, since the kernel then updates it on
+    /// the next use. `None` if the mount doesn't record access times.
+    pub fn idle_time(&self) -> Option<Duration> {
+        let metadata = fs::symlink_metadata(&self.socket_path).ok()?;
+        let last_used = metadata.accessed().ok()?;
+        let lag = match access_time_lag(&self.socket_path)? {
+            lag if last_used > last_changed(&metadata) => lag,
+            _ => Duration::ZERO,
+        };
+        let since = SystemTime::now().duration_since(last_used).ok()?;
+        Some(since.saturating_sub(lag))
+    }
+}
+
+/// The later of the modification and status change times in `metadata`.
+fn last_changed(metadata: &fs::Metadata) -> SystemTime {
+    let changed = u64::try_from(metadata.ctime())
+        .map(|secs| UNIX_EPOCH + Duration::new(secs, metadata.ctime_nsec() as u32))
+        .unwrap_or(UNIX_EPOCH);
+    metadata
+        .modified()
+        .map_or(changed, |modified| modified.max(changed))
+}
+
+/// How far the access time of the file at `path` can lag behind its last use, from the options
+/// of the mount holding it, or `None` if the mount doesn't record access times.
+///
+/// Without `/proc/self/mountinfo`, the `relatime` default is assumed.
+fn access_time_lag(path: &Path) -> Option<Duration> {
+    let Ok(mountinfo) = fs::read_to_string("/proc/self/mountinfo") else {
+        return Some(RELATIME_LAG);
+    };
+    // columns: id parent major:minor root mount-point options ...; the last mount over a path wins
+    let options = mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let mount_point = unescape_mount_point(fields.nth(4)?);
+            let options = fields.next()?;
+            path.starts_with(&mount_point)
+                .then_some((mount_point, options))
+        })
+        .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+        .map(|(_, options)| options.split(',').collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    if options.contains(&"noatime") {
+        None
+    } else if options.contains(&"relatime") || options.is_empty() {
+        Some(RELATIME_LAG)
+    } else {
+        // `strictatime`, which mountinfo shows as neither
+        Some(Duration::ZERO)
+    }
+}
+
+/// Undo the octal escapes mountinfo uses for whitespace and backslashes in mount points.
+fn unescape_mount_point(escaped: &str) -> PathBuf {
+    PathBuf::from(
+        escaped
+            .replace("\\040", " ")
+            .replace("\\011", "\t")
+            .replace("\\012", "\n")
+            .replace("\\134", "\\"),
+    )
+}
+
+/// Apply `action` to the unprotected agents holding identities that have been idle for longer than
+/// `threshold`, returning the agents still running and the number that couldn't be acted on.
+///
+/// `passphrase` is needed to lock agents.
+pub fn apply_idle_policy(
+    agents: Vec<Agent>,
+    threshold: Duration,
+    action: IdleAction,
+    passphrase: Option<&str>,
+) -> (Vec<Agent>, usize) {
+    let mut failures = 0;
+    let mut remaining = vec![];
+    for mut agent in agents {
+        let Some(idle) = agent.idle_time().filter(|idle| *idle > threshold) else {
+            remaining.push(agent);
+            continue;
+        };
+        if !matches!(
+            agent.check_agent_identities(),
+            Ok(AgentIdentityStatus::Identities(_))
+        ) {
+            remaining.push(agent);
+            continue;
+        }
+        if agent.is_spared() {
+            remaining.push(agent);
+            continue;
+        }
+
+        let result = match action {
+            IdleAction::Lock => match passphrase {
+                Some(passphrase) => agent
+                    .client()
+                    .and_then(|mut c| c.lock(passphrase))
+                    .inspect(|()| agent.remember_lock(true)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no passphrase to lock it with",
+                )),
+            },
+            IdleAction::Clear => agent.remove_all_identities(),
+            IdleAction::Kill => {
+                eprintln!(
+                    "Agent pid {} has been idle for {}",
+                    agent.pid,
+                    format_duration(idle)
+                );
+                agent.kill_and_clean_agent();
+                continue;
+            }
+        };
+        let (done, verb) = match action {
+            IdleAction::Lock => ("locked", "lock"),
+            _ => ("cleared", "clear"),
+        };
+        match result {
+            Ok(()) => eprintln!(
+                "Agent pid {} {} after being idle for {}",
+                agent.pid,
+                done,
+                format_duration(idle)
+            ),
+            Err(e) => {
+                eprintln!("Unable to {} idle agent pid {}: {}", verb, agent.pid, e);
+                failures += 1;
+            }
+        }
+        remaining.push(agent);
+    }
+    (remaining, failures)
+}
+
+/// Set the access time of the file at `path` to `time`, leaving its modification time alone.
+pub(crate) fn set_access_time(path: &Path, time: SystemTime) -> io::Result<()> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let times = [
+        libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+        },
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+    ];
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `path` is a valid C string and `times` holds the two timestamps utimensat reads
+    let result = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
@@ -1,11 +1,28 @@
+pub mod certificates;
+pub mod current;
+pub mod diff;
 pub mod files;
+pub mod health;
 pub mod identities;
+pub mod idle;
+pub mod protocol;
+pub mod references;
 pub mod running_agents;
+pub mod selector;
+pub mod spawner;
+pub mod state;
+pub mod systemd;
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::process::Stdio;
 
+use crate::config;
+use crate::publish::{self, stable_socket};
+use crate::shell::ShellFormat;
+
+use self::health::{AgentHealth, AgentProbe};
 use self::identities::AgentIdentityStatus;
 
 /// The SSH agent concept struct.
@@ -14,43 +31,172 @@ pub struct Agent {
     pub pid: String,
     pub socket_path: PathBuf,
     pub is_running: bool,
+    /// Whether this is the agent in the caller's `SSH_AUTH_SOCK`.
+    pub is_current: bool,
+    /// The result of probing the agent, cached by `running_agents::probe_agents`.
+    pub probe: Option<AgentProbe>,
 }
 
 impl Display for Agent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let health = self.probe_health();
+        let status = match health {
+            AgentHealth::Hung => format!(
+                "Hung, no answer within {}ms",
+                config::get().health.timeout_ms
+            ),
+            AgentHealth::Locked => AgentIdentityStatus::Locked.to_string(),
+            AgentHealth::NotASocket => "Not a socket".to_string(),
+            AgentHealth::PermissionDenied => "Permission denied".to_string(),
+            _ => match self.check_agent_identities() {
+                Ok(status @ AgentIdentityStatus::Identities(_)) => {
+                    match self.expired_certificate_count() {
+                        0 => status.to_string(),
+                        1 => format!("{}, 1 EXPIRED certificate", status),
+                        n => format!("{}, {} EXPIRED certificates", status, n),
+                    }
+                }
+                Ok(status) => status.to_string(),
+                Err(e) => format!("Unable to list identities ({})", e),
+            },
+        };
         write!(
             f,
-            "PID {}: {} at {} ({})",
+            "PID {}{}: {} at {} ({}{}{}{}{}{})",
             &self.pid,
-            self.check_agent_identities().unwrap(),
+            match self.label() {
+                Some(label) => format!(" [{}]", label),
+                None => String::new(),
+            },
+            status,
             &self.socket_path.display(),
-            if self.is_running { "Running" } else { "Dead" }
+            if self.is_running { "Running" } else { "Dead" },
+            match health {
+                AgentHealth::Slow(_) => format!(", {}", health),
+                _ => String::new(),
+            },
+            if self.is_foreign() { ", foreign" } else { "" },
+            if self.is_current { ", current" } else { "" },
+            if self.is_systemd_managed() {
+                ", systemd"
+            } else {
+                ""
+            },
+            if self.is_pinned() { ", pinned" } else { "" }
         )
     }
 }
 
 impl Agent {
     /// Print the env value exports that would be set by an initialization.
+    ///
+    /// The commands use the shell syntax from the configuration.
     pub fn print_env_commands(&self) {
-        println!("export SSH_AUTH_SOCK={:?}", self.socket_path);
-        println!("export SSH_AGENT_PID={}", self.pid);
+        println!("{}", self.env_commands(config::get().output.shell));
+    }
+
+    /// The commands setting the environment for the agent in the `shell` syntax, one per line.
+    ///
+    /// They point at the stable socket instead of the agent's own socket when it is configured to
+    /// be exported.
+    pub fn env_commands(&self, shell: ShellFormat) -> String {
+        let socket_path = stable_socket::exported_path(self);
+        format!(
+            "{}\n{}",
+            shell.export("SSH_AUTH_SOCK", &format!("{:?}", socket_path)),
+            shell.export("SSH_AGENT_PID", &self.pid)
+        )
+    }
+
+    /// Why the agent is protected from purges, reductions and the daemon, if it is.
+    ///
+    /// An agent whose pin can't be checked is protected, so an unreadable state file never gets a
+    /// pinned agent killed, and so is one whose keys can't be listed while `protect.fingerprints`
+    /// is set.
+    pub fn protection(&self) -> Option<String> {
+        let config = config::get();
+        if self.is_foreign() {
+            return Some("it isn't an `ssh-agent` process".to_string());
+        }
+        match self.pinned() {
+            Ok(true) => return Some("it is pinned".to_string()),
+            Ok(false) => {}
+            Err(e) => return Some(format!("its pin can't be checked, {}", e)),
+        }
+        if self.is_systemd_managed() {
+            return Some("it is managed by systemd".to_string());
+        }
+        if config.safety.protect_current && self.is_current {
+            return Some("it is the current agent and `safety.protect_current` is set".to_string());
+        }
+        if let Some(pattern) = config.protect.sockets.iter().find(|pattern| {
+            glob::Pattern::new(pattern).is_ok_and(|p| p.matches_path(&self.socket_path))
+        }) {
+            return Some(format!(
+                "its socket matches `{}` in `protect.sockets`",
+                pattern
+            ));
+        }
+        if !config.protect.fingerprints.is_empty() {
+            // a locked agent lists no identities, so it may hold a protected key all the same
+            let held = match self.check_agent_identities() {
+                Ok(AgentIdentityStatus::Locked) => Err("it is locked".to_string()),
+                _ => self.identity_fingerprints().map_err(|e| e.to_string()),
+            };
+            let held = match held {
+                Ok(held) => held,
+                Err(e) => {
+                    return Some(format!(
+                        "its keys can't be checked against `protect.fingerprints`, {}",
+                        e
+                    ))
+                }
+            };
+            if let Some(fingerprint) = config
+                .protect
+                .fingerprints
+                .iter()
+                .find(|fp| held.contains(fp))
+            {
+                return Some(format!(
+                    "it holds {} from `protect.fingerprints`",
+                    fingerprint
+                ));
+            }
+        }
+        None
+    }
+
+    /// Whether the agent is protected from purges, reductions and the daemon.
+    pub fn is_protected(&self) -> bool {
+        self.protection().is_some()
+    }
+
+    /// Whether the agent must be spared from being killed, explaining why on stderr if so.
+    pub fn is_spared(&self) -> bool {
+        match self.protection() {
+            Some(reason) => {
+                eprintln!("Leaving agent pid {} alone: {}", self.pid, reason);
+                true
+            }
+            None => false,
+        }
     }
 
     /// Kill the agent and ensure the socket paths are cleaned afterwards.
     ///
-    /// If the agent was alive when this function is called, the `ssh-agent` invocation will handle
-    /// the path and process clean up. If it was not alive, Agency will attempt to handle these
-    /// clean up steps.
+    /// If the agent is alive when this function is called, the `ssh-agent` invocation will handle
+    /// the path and process clean up. If it is not alive, Agency will attempt to handle these
+    /// clean up steps, without signalling anything.
     pub fn kill_and_clean_agent(&mut self) {
-        let started_as_running = self.is_running;
-        self.kill_agent();
-        if started_as_running {
+        if self.is_running {
+            self.kill_agent();
             return;
         }
 
         match self.clean_dead_agent_socket() {
             Ok(()) => {
-                println!(
+                eprintln!(
                     "Removed dead agent's socket: {}",
                     &self.socket_path.display()
                 );
@@ -65,9 +211,55 @@ impl Agent {
         }
     }
 
+    /// Whether the agent's pid is a process of the configured `ssh-agent` binary, going by
+    /// `/proc/<pid>/exe` or `comm`, or by `ps` without `/proc`.
+    ///
+    /// Nothing is signalled without this, so a process that only holds an agent socket, like sshd
+    /// forwarding an agent, is never killed, whether or not its socket answers.
+    pub fn is_ssh_agent_process(&self) -> bool {
+        let Some(name) = config::get().binaries.ssh_agent.file_name() else {
+            return false;
+        };
+        if self.pid.parse::<u32>().is_err() {
+            return false;
+        }
+
+        let proc_dir = Path::new("/proc").join(&self.pid);
+        if !Path::new("/proc/self").exists() {
+            return Command::new("ps")
+                .args(["-o", "comm=", "-p", &self.pid])
+                .output()
+                .is_ok_and(|output| {
+                    let comm = String::from_utf8_lossy(&output.stdout);
+                    Path::new(comm.trim()).file_name() == Some(name)
+                });
+        }
+        if let Ok(exe) = fs::read_link(proc_dir.join("exe")) {
+            return exe.file_name() == Some(name);
+        }
+        // the kernel truncates names to 15 bytes
+        let name: String = name.to_string_lossy().chars().take(15).collect();
+        fs::read_to_string(proc_dir.join("comm")).is_ok_and(|comm| comm.trim_end() == name)
+    }
+
+    /// Whether the agent is held by a process other than `ssh-agent`, like a forwarded agent.
+    pub fn is_foreign(&self) -> bool {
+        self.is_running && !self.is_ssh_agent_process()
+    }
+
     /// Kill the agent via the `ssh-agent` tool.
+    ///
+    /// If this was the caller's current agent, `unset` commands for its environment are printed.
     pub fn kill_agent(&mut self) {
-        match Command::new("ssh-agent")
+        if !self.is_ssh_agent_process() {
+            eprintln!(
+                "Not killing pid {}: it isn't an `ssh-agent` process",
+                self.pid
+            );
+            return;
+        }
+
+        match Command::new(&config::get().binaries.ssh_agent)
             .arg("-k")
             .env(
                 "SSH_AUTH_SOCK",
@@ -80,7 +272,13 @@ impl Agent {
             Ok(status) => {
                 if status.success() {
                     self.is_running = false;
-                    println!("Agent pid {} killed", self.pid);
+                    publish::retract(self);
+                    eprintln!("Agent pid {} killed", self.pid);
+                    if self.is_current {
+                        let shell = config::get().output.shell;
+                        println!("{}", shell.unset("SSH_AUTH_SOCK"));
+                        println!("{}", shell.unset("SSH_AGENT_PID"));
+                    }
                 } else {
                     eprintln!("Failed to kill agent pid {}", self.pid);
                 }
@@ -89,45 +287,24 @@ impl Agent {
         }
     }
 
-    /// Check the identities present on the agent.
+    /// Check the identities present on the agent, from the cached probe if there is one.
     ///
     /// If the agent is not alive, this will return `AgentIdentityStatus::ConnectionRefused`.
     pub fn check_agent_identities(
         &self,
     ) -> Result<AgentIdentityStatus, Box<dyn std::error::Error>> {
-        match Command::new("ssh-add")
-            .arg("-l")
-            .env(
-                "SSH_AUTH_SOCK",
-                self.socket_path.to_str().unwrap_or_default(),
-            )
-            // the PID may not be required, funnily enough
-            .env("SSH_AGENT_PID", &self.pid)
-            .output()
-        {
-            Ok(o) => {
-                let output = String::from_utf8(o.stdout);
-                let identity_list = output.unwrap_or("Something went wrong".to_string());
-                let identity_list = identity_list.trim().split("\n").collect::<Vec<&str>>();
-
-                let stderr = String::from_utf8(o.stderr).unwrap_or_default();
-
-                if stderr
-                    .trim()
-                    .contains("Could not open a connection to your authentication agents.")
-                {
-                    return Ok(AgentIdentityStatus::ConnectionRefused);
-                }
+        let probe = self.probe();
+        if probe.health == AgentHealth::Hung {
+            return Err(probe.identities().unwrap_err().into());
+        }
 
-                match identity_list[..] {
-                    ["The agent has no identities."] => Ok(AgentIdentityStatus::NoIdentities),
-                    _ => Ok(AgentIdentityStatus::Identities(identity_list.len() as i32)),
-                }
+        Ok(match &probe.identities {
+            None => AgentIdentityStatus::ConnectionRefused,
+            Some(identities) if !identities.is_empty() => {
+                AgentIdentityStatus::Identities(identities.len() as i32)
             }
-            Err(e) => {
-                println!("Error checking agent {}: {:?}", &self.pid, e);
-                Err(Box::new(e))
-            }
-        }
+            Some(_) if self.is_locked_by_agency() => AgentIdentityStatus::Locked,
+            Some(_) => AgentIdentityStatus::NoIdentities,
+        })
     }
 }
@@ -0,0 +1,243 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use ssh_encoding::{Decode, Encode};
+use ssh_key::public::KeyData;
+use ssh_key::{Algorithm, Certificate, HashAlg, PrivateKey, PublicKey, Signature};
+
+// Message numbers from the SSH agent protocol (draft-miller-ssh-agent)
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENT_SUCCESS: u8 = 6;
+const SSH2_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH2_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH2_AGENTC_ADD_IDENTITY: u8 = 17;
+const SSH2_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH2_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH2_AGENTC_REMOVE_IDENTITY: u8 = 18;
+const SSH2_AGENTC_REMOVE_ALL_IDENTITIES: u8 = 19;
+const SSH_AGENTC_LOCK: u8 = 22;
+const SSH_AGENTC_UNLOCK: u8 = 23;
+const SSH2_AGENTC_ADD_ID_CONSTRAINED: u8 = 25;
+
+const SSH_AGENT_CONSTRAIN_LIFETIME: u8 = 1;
+const SSH_AGENT_CONSTRAIN_CONFIRM: u8 = 2;
+
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// The largest reply accepted from an agent, matching OpenSSH's own limit.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// An identity held by an agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// The public key or certificate in SSH wire format.
+    pub key_blob: Vec<u8>,
+    pub comment: String,
+}
+
+impl Identity {
+    /// The plain public key of the identity, which is the certified key for certificates.
+    pub fn key_data(&self) -> Option<KeyData> {
+        match self.certificate() {
+            Some(certificate) => Some(certificate.public_key().clone()),
+            None => KeyData::decode(&mut self.key_blob.as_slice()).ok(),
+        }
+    }
+
+    /// The plain public key of the identity with its comment, as found in `.pub` files.
+    pub fn public_key(&self) -> Option<PublicKey> {
+        self.key_data()
+            .map(|key_data| PublicKey::new(key_data, &self.comment))
+    }
+
+    /// The certificate, if the identity is one.
+    pub fn certificate(&self) -> Option<Certificate> {
+        Certificate::from_bytes(&self.key_blob).ok()
+    }
+
+    /// The SHA256 fingerprint of the identity, as shown by `ssh-add -l`.
+    pub fn fingerprint(&self) -> String {
+        match self.key_data() {
+            Some(key_data) => key_data.fingerprint(HashAlg::Sha256).to_string(),
+            None => "unknown".to_string(),
+        }
+    }
+}
+
+/// Restrictions on how an agent may use an added identity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Constraints {
+    /// Remove the identity after this long.
+    pub lifetime: Option<Duration>,
+    /// Ask the user to confirm each use of the identity.
+    pub confirm: bool,
+}
+
+/// A client speaking the SSH agent protocol over an agent's socket.
+pub struct AgentClient {
+    stream: UnixStream,
+}
+
+impl AgentClient {
+    /// Connect to the agent at `socket_path`, giving up on any request it doesn't answer within
+    /// `timeout`.
+    pub fn connect(socket_path: &Path, timeout: Duration) -> io::Result<Self> {
+        let stream = UnixStream::connect(socket_path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Self { stream })
+    }
+
+    /// Send a message and read the agent's reply as its type and contents.
+    fn request(&mut self, message_type: u8, contents: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let len = u32::try_from(contents.len() + 1).map_err(io::Error::other)?;
+        let mut message = Vec::with_capacity(contents.len() + 5);
+        message.extend_from_slice(&len.to_be_bytes());
+        message.push(message_type);
+        message.extend_from_slice(contents);
+        self.stream.write_all(&message).map_err(timed_out)?;
+
+        let mut len = [0u8; 4];
+        self.stream.read_exact(&mut len).map_err(timed_out)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("agent sent a reply of invalid length {}", len),
+            ));
+        }
+
+        let mut reply = vec![0u8; len];
+        self.stream.read_exact(&mut reply).map_err(timed_out)?;
+        let reply_type = reply.remove(0);
+        Ok((reply_type, reply))
+    }
+
+    /// Send a message that the agent answers with success or failure.
+    fn request_success(&mut self, message_type: u8, contents: &[u8]) -> io::Result<()> {
+        match self.request(message_type, contents)? {
+            (SSH_AGENT_SUCCESS, _) => Ok(()),
+            (SSH_AGENT_FAILURE, _) => Err(io::Error::other("agent refused the request")),
+            (other, _) => Err(unexpected_reply(other)),
+        }
+    }
+
+    /// List the identities held by the agent.
+    pub fn request_identities(&mut self) -> io::Result<Vec<Identity>> {
+        let (reply_type, reply) = self.request(SSH2_AGENTC_REQUEST_IDENTITIES, &[])?;
+        if reply_type != SSH2_AGENT_IDENTITIES_ANSWER {
+            return Err(unexpected_reply(reply_type));
+        }
+
+        let mut reader = reply.as_slice();
+        let count = u32::decode(&mut reader).map_err(invalid_data)?;
+        (0..count)
+            .map(|_| {
+                Ok(Identity {
+                    key_blob: Vec::<u8>::decode(&mut reader).map_err(invalid_data)?,
+                    comment: String::decode(&mut reader).map_err(invalid_data)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Add a decrypted private key to the agent.
+    pub fn add_identity(&mut self, key: &PrivateKey, constraints: Constraints) -> io::Result<()> {
+        let mut contents = vec![];
+        key.key_data()
+            .encode(&mut contents)
+            .and_then(|_| key.comment().encode(&mut contents))
+            .map_err(invalid_data)?;
+
+        if let Some(lifetime) = constraints.lifetime {
+            contents.push(SSH_AGENT_CONSTRAIN_LIFETIME);
+            let seconds = u32::try_from(lifetime.as_secs()).unwrap_or(u32::MAX);
+            contents.extend_from_slice(&seconds.to_be_bytes());
+        }
+        if constraints.confirm {
+            contents.push(SSH_AGENT_CONSTRAIN_CONFIRM);
+        }
+
+        let message_type = if constraints == Constraints::default() {
+            SSH2_AGENTC_ADD_IDENTITY
+        } else {
+            SSH2_AGENTC_ADD_ID_CONSTRAINED
+        };
+        self.request_success(message_type, &contents)
+    }
+
+    /// Remove the identity with the public key or certificate `key_blob` from the agent.
+    pub fn remove_identity(&mut self, key_blob: &[u8]) -> io::Result<()> {
+        let mut contents = vec![];
+        key_blob.encode(&mut contents).map_err(invalid_data)?;
+        self.request_success(SSH2_AGENTC_REMOVE_IDENTITY, &contents)
+    }
+
+    /// Remove every identity from the agent.
+    pub fn remove_all_identities(&mut self) -> io::Result<()> {
+        self.request_success(SSH2_AGENTC_REMOVE_ALL_IDENTITIES, &[])
+    }
+
+    /// Ask the agent to sign `data` with `identity`.
+    ///
+    /// RSA identities are asked for `rsa-sha2-512` signatures rather than the SHA-1 based default.
+    pub fn sign(&mut self, identity: &Identity, data: &[u8]) -> io::Result<Signature> {
+        let flags = match identity.key_data().map(|k| k.algorithm()) {
+            Some(Algorithm::Rsa { .. }) => SSH_AGENT_RSA_SHA2_512,
+            _ => 0,
+        };
+        let mut contents = vec![];
+        identity
+            .key_blob
+            .encode(&mut contents)
+            .and_then(|_| data.encode(&mut contents))
+            .and_then(|_| flags.encode(&mut contents))
+            .map_err(invalid_data)?;
+
+        match self.request(SSH2_AGENTC_SIGN_REQUEST, &contents)? {
+            (SSH2_AGENT_SIGN_RESPONSE, reply) => {
+                let signature = Vec::<u8>::decode(&mut reply.as_slice()).map_err(invalid_data)?;
+                Signature::decode(&mut signature.as_slice()).map_err(invalid_data)
+            }
+            (SSH_AGENT_FAILURE, _) => Err(io::Error::other("agent refused to sign")),
+            (other, _) => Err(unexpected_reply(other)),
+        }
+    }
+
+    /// Lock the agent with `passphrase`, hiding its identities until it is unlocked.
+    pub fn lock(&mut self, passphrase: &str) -> io::Result<()> {
+        let mut contents = vec![];
+        passphrase.encode(&mut contents).map_err(invalid_data)?;
+        self.request_success(SSH_AGENTC_LOCK, &contents)
+    }
+
+    /// Unlock the agent with the `passphrase` it was locked with.
+    pub fn unlock(&mut self, passphrase: &str) -> io::Result<()> {
+        let mut contents = vec![];
+        passphrase.encode(&mut contents).map_err(invalid_data)?;
+        self.request_success(SSH_AGENTC_UNLOCK, &contents)
+    }
+}
+
+fn unexpected_reply(reply_type: u8) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("agent sent unexpected reply type {}", reply_type),
+    )
+}
+
+/// Report socket timeouts, which show up as `WouldBlock` on Unix, as `TimedOut`.
+fn timed_out(e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            io::Error::new(io::ErrorKind::TimedOut, "agent did not answer in time")
+        }
+        _ => e,
+    }
+}
+
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// The sockets other processes have as their `SSH_AUTH_SOCK`, with symlinks like the stable
+/// socket also resolved to the socket they point at.
+///
+/// Only the environments of processes this user may read are seen.
+pub fn referenced_sockets() -> HashSet<PathBuf> {
+    let own_pid = std::process::id().to_string();
+    let mut sockets = HashSet::new();
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return sockets;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name == own_pid || !name.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(environ) = fs::read(entry.path().join("environ")) else {
+            continue;
+        };
+        let Some(socket) = environ
+            .split(|b| *b == 0)
+            .find_map(|var| var.strip_prefix(b"SSH_AUTH_SOCK="))
+            .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+        else {
+            continue;
+        };
+
+        if let Ok(target) = fs::read_link(&socket) {
+            sockets.insert(target);
+        }
+        sockets.insert(socket);
+    }
+    sockets
+}
@@ -1,9 +1,25 @@
 use std::{
+    collections::HashMap,
     fs, io,
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
     process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    thread,
 };
 
-use super::{Agent, AgentIdentityStatus};
+use crate::config;
+
+use super::{
+    current::is_current_socket,
+    health::{AgentHealth, AgentProbe},
+    references::referenced_sockets,
+    systemd::systemd_agent_socket,
+    Agent, AgentIdentityStatus,
+};
 
 /// The possible states of agents running on the system.
 pub enum RunningAgentCheckStatus {
@@ -12,50 +28,192 @@ pub enum RunningAgentCheckStatus {
     NoAgents,
 }
 
-/// Kill and clean live agents that have no identities registered while guaranteeing at least one
-/// stays alive.
+/// Kill and clean live agents that have no identities registered, or that are hung, while
+/// guaranteeing at least one stays alive.
+///
+/// Protected agents are never killed.
 pub fn purge_empty_agents_retain_one(agents: Vec<Agent>) -> Vec<Agent> {
-    let (mut empty_agents, mut other_agents): (Vec<Agent>, Vec<Agent>) = agents
-        .into_iter()
-        .partition(|a| match Agent::check_agent_identities(&a) {
-            Ok(AgentIdentityStatus::NoIdentities) => true,
-            _ => false,
-        });
-
-    if other_agents.len() == 0 && !empty_agents.is_empty() {
-        let empty_last = empty_agents.pop().unwrap();
-        other_agents.push(empty_last);
+    let (mut purgeable, mut other_agents): (Vec<Agent>, Vec<Agent>) =
+        agents.into_iter().partition(is_purgeable);
+
+    if other_agents.is_empty() {
+        // only an empty agent is worth keeping; a hung one is no use to anyone
+        if let Some(i) = purgeable
+            .iter()
+            .rposition(|a| a.probe_health() != AgentHealth::Hung)
+        {
+            other_agents.push(purgeable.remove(i));
+        }
     }
 
-    for mut a in empty_agents {
+    for mut a in purgeable {
         a.kill_and_clean_agent();
     }
 
     other_agents
 }
 
-/// Kill and clean all live agents that have no identities registered.
+/// Kill and clean all live agents that have no identities registered, or that are hung.
+///
+/// Protected agents are never killed.
 pub fn purge_empty_agents(agents: Vec<Agent>) -> Vec<Agent> {
-    let (empty_agents, other_agents): (Vec<Agent>, Vec<Agent>) =
-        agents
-            .into_iter()
-            .partition(|a| match Agent::check_agent_identities(&a) {
-                Ok(AgentIdentityStatus::NoIdentities) => true,
-                _ => false,
-            });
+    let (purgeable, other_agents): (Vec<Agent>, Vec<Agent>) =
+        agents.into_iter().partition(is_purgeable);
 
-    for mut a in empty_agents {
+    for mut a in purgeable {
         a.kill_and_clean_agent();
     }
 
     other_agents
 }
 
+/// Kill and clean the agents `purge_empty_agents` would, but only the ones no other process has as
+/// its `SSH_AUTH_SOCK`.
+///
+/// Protected agents are never killed.
+pub fn purge_empty_orphans(agents: Vec<Agent>) -> Vec<Agent> {
+    let referenced = referenced_sockets();
+    let (orphans, other_agents): (Vec<Agent>, Vec<Agent>) = agents
+        .into_iter()
+        .partition(|a| !referenced.contains(&a.socket_path) && is_purgeable(a));
+
+    for mut a in orphans {
+        eprintln!(
+            "Agent pid {} at {} is empty or hung and no process uses it",
+            a.pid,
+            a.socket_path.display()
+        );
+        a.kill_and_clean_agent();
+    }
+
+    other_agents
+}
+
+/// Whether a purge may kill `agent`: it is either hung or without identities, and unprotected.
+///
+/// Protected agents that would otherwise be purged are reported on stderr.
+fn is_purgeable(agent: &Agent) -> bool {
+    let empty_or_hung = match agent.probe_health() {
+        AgentHealth::Hung => true,
+        health if health.is_responsive() => matches!(
+            agent.check_agent_identities(),
+            Ok(AgentIdentityStatus::NoIdentities)
+        ),
+        _ => false,
+    };
+    empty_or_hung && !agent.is_spared()
+}
+
+/// The methods available for consolidating several agents down to one.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ReductionStrategy {
+    /// Keep the agent with the most registered identities.
+    #[default]
+    Count,
+    /// Keep an agent with no particular method.
+    Simple,
+}
+
+/// The number of identities on `agent`, with unreachable and hung agents ranked below empty ones.
+fn identity_count(agent: &Agent) -> i32 {
+    match agent.check_agent_identities() {
+        Ok(AgentIdentityStatus::NoIdentities | AgentIdentityStatus::Locked) => 0,
+        Ok(AgentIdentityStatus::Identities(c)) => c,
+        Ok(AgentIdentityStatus::ConnectionRefused) | Err(_) => -1,
+    }
+}
+
+/// Order `agents` so the agent that `strategy` would keep comes first.
+///
+/// The agent the calling shell currently uses is always preferred over the others.
+fn rank_agents(agents: &mut [Agent], strategy: ReductionStrategy) {
+    match strategy {
+        ReductionStrategy::Count => {
+            agents.sort_by_cached_key(|a| (!a.is_current, std::cmp::Reverse(identity_count(a))))
+        }
+        ReductionStrategy::Simple => agents.sort_by_key(|a| !a.is_current),
+    }
+}
+
+/// Pick the agent that `strategy` would keep, without killing any of the others.
+pub fn pick_agent(agents: &[Agent], strategy: ReductionStrategy) -> Option<Agent> {
+    let mut agents = agents.to_vec();
+    rank_agents(&mut agents, strategy);
+    agents.into_iter().next()
+}
+
+/// Kill and clean every agent except the one that `strategy` would keep, returning the survivor.
+///
+/// Protected agents are never killed, whether or not they survive.
+pub fn reduce_agents(mut agents: Vec<Agent>, strategy: ReductionStrategy) -> Option<Agent> {
+    rank_agents(&mut agents, strategy);
+    let kill_queue: Vec<Agent> = agents.drain(1.min(agents.len())..).collect();
+    for mut a in kill_queue.into_iter().filter(|a| !a.is_spared()) {
+        a.kill_and_clean_agent();
+    }
+    agents.pop()
+}
+
+/// Kill and clean the agents ranked below the first `max` by `strategy`, returning the others.
+///
+/// Protected agents are never killed, so more than `max` agents may survive.
+pub fn enforce_max_agents(
+    mut agents: Vec<Agent>,
+    max: usize,
+    strategy: ReductionStrategy,
+) -> Vec<Agent> {
+    rank_agents(&mut agents, strategy);
+    let excess: Vec<Agent> = agents.drain(max.min(agents.len())..).collect();
+    for mut a in excess {
+        if a.is_spared() {
+            agents.push(a);
+            continue;
+        }
+        eprintln!(
+            "Agent pid {} at {} is over the `max_agents` limit of {}",
+            a.pid,
+            a.socket_path.display(),
+            max
+        );
+        a.kill_and_clean_agent();
+    }
+    agents
+}
+
+/// Remove the sockets of `dead_agents`, reporting each on stderr.
+pub fn clean_dead_agents(dead_agents: &[Agent]) {
+    for a in dead_agents {
+        match a.clean_dead_agent_socket() {
+            Ok(()) => {
+                eprintln!("Removed dead agent's socket: {}", &a.socket_path.display());
+            }
+            Err(e) => {
+                eprintln!(
+                    "Unable to remove socket for agent at {}: {}",
+                    &a.socket_path.display(),
+                    e,
+                );
+            }
+        }
+    }
+}
+
 /// Build a `RunningAgentCheckStatus` from the list of agents.
 ///
 /// If the list has one agent, `RunningAgentCheckStatus::SingleAgent(Agent)` will take ownership of
 /// the agent.
-pub fn check_agents(agents: &Vec<Agent>) -> RunningAgentCheckStatus {
+pub fn check_agents(agents: &[Agent]) -> RunningAgentCheckStatus {
     match agents.len() {
         0 => RunningAgentCheckStatus::NoAgents,
         1 => RunningAgentCheckStatus::SingleAgent(agents.first().unwrap().clone()),
@@ -63,28 +221,116 @@ pub fn check_agents(agents: &Vec<Agent>) -> RunningAgentCheckStatus {
     }
 }
 
+/// Resolve the pids for the `agents` found from the existing sockets.
+///
+/// Only agents whose socket is held open by a live process are returned, marked as running. On
+/// systems with a `/proc` filesystem the owning process is looked up exactly, and kept only if it
+/// is `ssh-agent` or answers like an agent, such as a forwarded agent; elsewhere the pids are
+/// guessed from the socket paths.
+pub fn resolve_agent_pids(agents: &[Agent]) -> Vec<Agent> {
+    let agents = match listening_socket_owners() {
+        Some(owners) => agents
+            .iter()
+            .filter_map(|a| {
+                owners.get(&a.socket_path).map(|pid| Agent {
+                    pid: pid.to_string(),
+                    is_running: true,
+                    ..a.clone()
+                })
+            })
+            .filter(|a| {
+                a.is_ssh_agent_process()
+                    || a.client()
+                        .and_then(|mut client| client.request_identities())
+                        .is_ok()
+            })
+            .collect(),
+        None => guess_agent_pids(agents),
+    };
+
+    agents
+        .into_iter()
+        .map(|a| Agent {
+            is_current: is_current_socket(&a.socket_path),
+            ..a
+        })
+        .collect()
+}
+
+/// Map the path of every listening Unix socket to the lowest pid holding it open.
+///
+/// Returns `None` if `/proc` isn't available.
+fn listening_socket_owners() -> Option<HashMap<PathBuf, u32>> {
+    // columns: Num RefCount Protocol Flags Type St Inode Path
+    const SO_ACCEPTCON: u32 = 0x10000;
+    let unix_sockets = fs::read_to_string("/proc/net/unix").ok()?;
+    let listening: HashMap<u64, PathBuf> = unix_sockets
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+            let inode = fields.get(6)?.parse::<u64>().ok()?;
+            let path = fields.get(7)?;
+            (flags & SO_ACCEPTCON != 0).then(|| (inode, PathBuf::from(path)))
+        })
+        .collect();
+
+    let mut owners: HashMap<PathBuf, u32> = HashMap::new();
+    for proc_entry in fs::read_dir("/proc").ok()?.filter_map(|e| e.ok()) {
+        let Some(pid) = proc_entry
+            .file_name()
+            .to_str()
+            .and_then(|p| p.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(proc_entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.filter_map(|fd| fd.ok()) {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|t| t.strip_prefix("socket:["))
+                .and_then(|t| t.strip_suffix(']'))
+                .and_then(|t| t.parse::<u64>().ok());
+            if let Some(path) = inode.and_then(|i| listening.get(&i)) {
+                let owner = owners.entry(path.clone()).or_insert(pid);
+                *owner = (*owner).min(pid);
+            }
+        }
+    }
+
+    Some(owners)
+}
+
 /// Guess the pids for the `agents` found from the existing sockets.
 ///
 /// The "guessing" relies on the PID and the socket path having similar numeric identifiers. If
 /// this assumption is broken, the application may incorrectly assign a PID to an agent.
-pub fn resolve_agent_pids(agents: &Vec<Agent>) -> Vec<Agent> {
-    let ps_child = Command::new("ps")
+fn guess_agent_pids(agents: &[Agent]) -> Vec<Agent> {
+    let mut ps_child = Command::new("ps")
         .arg("-ef")
         .stdout(Stdio::piped())
         .spawn()
         .unwrap();
-    let grep_1_child = Command::new("grep")
+    let mut grep_1_child = Command::new("grep")
         .arg("ssh-agent -s")
-        .stdin(Stdio::from(ps_child.stdout.unwrap()))
+        .stdin(Stdio::from(ps_child.stdout.take().unwrap()))
         .stdout(Stdio::piped())
         .spawn()
         .unwrap();
     let grep_2_output = Command::new("grep")
         .arg("-v")
         .arg("grep")
-        .stdin(Stdio::from(grep_1_child.stdout.unwrap()))
+        .stdin(Stdio::from(grep_1_child.stdout.take().unwrap()))
         .output()
         .expect("failed to get running agents");
+    let _ = ps_child.wait();
+    let _ = grep_1_child.wait();
 
     let stdout = String::from_utf8(grep_2_output.stdout).unwrap_or_default();
     let agent_pids = stdout.split("\n").collect::<Vec<&str>>();
@@ -110,13 +356,45 @@ pub fn resolve_agent_pids(agents: &Vec<Agent>) -> Vec<Agent> {
         .map(|(pid, agent)| Agent {
             pid: pid.to_string(),
             is_running: true,
-            socket_path: agent.socket_path.clone(),
+            ..agent.clone()
         })
         .collect();
     agents_with_inferred_pids
 }
 
+/// Probe every agent once, a bounded number at a time, and cache the results on the agents.
+///
+/// Each probe waits at most the configured timeout, so a batch of hung agents takes about as long
+/// as one of them.
+pub fn probe_agents(agents: Vec<Agent>) -> Vec<Agent> {
+    let workers = config::get().health.workers.clamp(1, agents.len().max(1));
+    let next = AtomicUsize::new(0);
+    let probes: Vec<OnceLock<AgentProbe>> = agents.iter().map(|_| OnceLock::new()).collect();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(agent) = agents.get(i) else {
+                    break;
+                };
+                let _ = probes[i].set(agent.probe_now());
+            });
+        }
+    });
+
+    agents
+        .into_iter()
+        .zip(probes)
+        .map(|(a, probe)| Agent {
+            probe: probe.into_inner(),
+            ..a
+        })
+        .collect()
+}
+
 /// Filter the agents in `all_agents` that aren't in `running_agents`.
+///
+/// Sockets something still listens on are never dead, even if it isn't an agent.
 pub fn get_dead_agents(all_agents: Vec<Agent>, running_agents: Vec<Agent>) -> Vec<Agent> {
     // TODO: why doesn't this check `is_running`?
     all_agents
@@ -126,55 +404,82 @@ pub fn get_dead_agents(all_agents: Vec<Agent>, running_agents: Vec<Agent>) -> Ve
                 .iter()
                 .any(|r_a| r_a.socket_path == a.socket_path)
         })
+        .filter(|a| a.is_abandoned())
         .collect()
 }
 
-/// Get a list of candidate agents from the expected SSH agent directory.
+/// Get a list of candidate agents from the configured discovery paths.
+///
+/// Each discovery directory is searched for `agent.*` sockets, both directly inside it and inside
+/// `ssh-*` directories like the ones `ssh-agent` creates. Other sockets, like a database's in
+/// `/tmp`, are never taken for agents. A discovery path that is itself a socket is taken as an
+/// agent whatever it is called. Symlinks are skipped so stable links to an agent aren't reported
+/// as separate agents. The agent run by the `setup systemd` unit is found wherever discovery looks.
 ///
 /// The Agents returned by this function will all be marked as not running. They will be checked
 /// against the list of agent PIDs later to determine which agents are live.
 pub fn get_current_agents() -> io::Result<Vec<Agent>> {
-    // TODO: can this be other directories?
-    let agent_dirs: Vec<Agent> = fs::read_dir("/tmp")?
-        .filter_map(|res| match res {
-            Ok(res) => {
-                if res
-                    .path()
-                    .to_str()
-                    .unwrap_or_default()
-                    .starts_with("/tmp/ssh-")
-                {
-                    Some(res)
-                } else {
-                    None
+    let mut agents = vec![];
+    for path in &config::get().discovery.paths {
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            agents.push(agent_from_socket(path.clone()));
+            continue;
+        }
+
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_socket() && is_agent_socket_name(&entry) {
+                agents.push(agent_from_socket(entry.path()));
+            } else if file_type.is_dir() && entry.file_name().to_string_lossy().starts_with("ssh-")
+            {
+                let socket = fs::read_dir(entry.path())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|f| f.ok())
+                    .find(|f| {
+                        f.file_type().is_ok_and(|t| t.is_socket()) && is_agent_socket_name(f)
+                    });
+                if let Some(socket) = socket {
+                    agents.push(agent_from_socket(socket.path()));
                 }
             }
-            Err(_) => None,
-        })
-        .map(|dir| -> io::Result<Agent> {
-            let socket: Option<fs::DirEntry> = fs::read_dir(dir.path())?
-                .next()
-                .map(|f| if let Ok(f) = f { Some(f) } else { None })
-                .flatten();
-            if let Some(socket) = socket {
-                let pid = socket.file_name().into_string();
-                let pid = if let Ok(pid) = pid {
-                    pid.split(".").nth(1).unwrap_or_else(|| "N/A").to_string()
-                } else {
-                    "N/A".to_string()
-                };
-                Ok(Agent {
-                    pid,
-                    is_running: false,
-                    socket_path: socket.path(),
-                })
-            } else {
-                // TODO: use a better error
-                Err(io::Error::new(io::ErrorKind::Other, "argh"))
-            }
-        })
-        .filter_map(|a| a.ok())
-        .collect();
+        }
+    }
+
+    if let Some(socket) = systemd_agent_socket() {
+        let is_socket = fs::symlink_metadata(&socket).is_ok_and(|m| m.file_type().is_socket());
+        if is_socket && !agents.iter().any(|a| a.socket_path == socket) {
+            agents.push(agent_from_socket(socket));
+        }
+    }
 
-    Ok(agent_dirs)
+    Ok(agents)
+}
+
+/// Whether `entry` is named like the sockets `ssh-agent` creates.
+fn is_agent_socket_name(entry: &fs::DirEntry) -> bool {
+    entry.file_name().to_string_lossy().starts_with("agent.")
+}
+
+/// Build a not-yet-running agent for `socket_path`, guessing the PID from `agent.<ppid>` names.
+fn agent_from_socket(socket_path: PathBuf) -> Agent {
+    let pid = socket_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').nth(1))
+        .unwrap_or("N/A")
+        .to_string();
+    Agent {
+        pid,
+        socket_path,
+        ..Default::default()
+    }
 }
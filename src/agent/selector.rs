@@ -0,0 +1,197 @@
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use inquire::Select;
+
+use super::diff::diff_identities;
+use super::identities::key_file_fingerprint;
+use super::protocol::Identity;
+use super::Agent;
+
+/// A way of naming one of the running agents on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentSelector {
+    /// The agent in the caller's `SSH_AUTH_SOCK`, written as `current`.
+    Current,
+    /// The agent with this process ID.
+    Pid(String),
+    /// The agent listening on this socket path, written with at least one `/`.
+    Socket(PathBuf),
+    /// The agent given this label with `ssh-agency label`.
+    Label(String),
+}
+
+impl FromStr for AgentSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "current" {
+            Ok(AgentSelector::Current)
+        } else if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+            Ok(AgentSelector::Pid(s.to_string()))
+        } else if s.contains('/') {
+            Ok(AgentSelector::Socket(PathBuf::from(s)))
+        } else if !s.is_empty() {
+            Ok(AgentSelector::Label(s.to_string()))
+        } else {
+            Err("the agent can't be empty".to_string())
+        }
+    }
+}
+
+impl Display for AgentSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentSelector::Current => write!(f, "current"),
+            AgentSelector::Pid(pid) => write!(f, "{}", pid),
+            AgentSelector::Socket(path) => write!(f, "{}", path.display()),
+            AgentSelector::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+impl AgentSelector {
+    /// Whether this selector names `agent`.
+    pub fn matches(&self, agent: &Agent) -> bool {
+        match self {
+            AgentSelector::Current => agent.is_current,
+            AgentSelector::Pid(pid) => &agent.pid == pid,
+            AgentSelector::Socket(path) => &agent.socket_path == path,
+            AgentSelector::Label(label) => agent.label().as_ref() == Some(label),
+        }
+    }
+
+    /// Find the agent this selector names among `agents`.
+    pub fn select(&self, agents: &[Agent]) -> Option<Agent> {
+        agents.iter().find(|a| self.matches(a)).cloned()
+    }
+}
+
+/// A way of naming an identity on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyQuery {
+    /// The identity with this `SHA256:` fingerprint, given directly or read from a key file.
+    Fingerprint(String),
+    /// Identities whose comment contains this text.
+    Comment(String),
+}
+
+impl FromStr for KeyQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("SHA256:") {
+            Ok(KeyQuery::Fingerprint(s.to_string()))
+        } else if Path::new(s).exists() {
+            key_file_fingerprint(Path::new(s))
+                .map(KeyQuery::Fingerprint)
+                .ok_or_else(|| format!("`{}` is not a readable key file", s))
+        } else if !s.is_empty() {
+            Ok(KeyQuery::Comment(s.to_string()))
+        } else {
+            Err("the key can't be empty".to_string())
+        }
+    }
+}
+
+impl Display for KeyQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyQuery::Fingerprint(fingerprint) => write!(f, "{}", fingerprint),
+            KeyQuery::Comment(comment) => write!(f, "comment `{}`", comment),
+        }
+    }
+}
+
+impl KeyQuery {
+    /// Whether this query names `identity`.
+    pub fn matches(&self, identity: &Identity) -> bool {
+        match self {
+            KeyQuery::Fingerprint(fingerprint) => &identity.fingerprint() == fingerprint,
+            KeyQuery::Comment(comment) => identity.comment.contains(comment.as_str()),
+        }
+    }
+
+    /// Find the agents among `agents` holding an identity this query names, with the agent in
+    /// the caller's `SSH_AUTH_SOCK` first.
+    pub fn holders(&self, agents: &[Agent]) -> Vec<Agent> {
+        let mut holders: Vec<Agent> = agents
+            .iter()
+            .filter(|a| {
+                a.identities()
+                    .is_ok_and(|ids| ids.iter().any(|id| self.matches(id)))
+            })
+            .cloned()
+            .collect();
+        holders.sort_by_key(|a| !a.is_current);
+        holders
+    }
+}
+
+/// Pick one of the running `agents` with the `selector`, or ask the user if there's no selector.
+///
+/// A single running agent is picked without asking. When asking, the agent in the caller's
+/// `SSH_AUTH_SOCK` is preselected.
+pub fn choose_agent(
+    selector: Option<&AgentSelector>,
+    agents: &[Agent],
+    message: &str,
+) -> io::Result<Agent> {
+    if let Some(selector) = selector {
+        return selector.select(agents).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no running agent matches `{}`", selector),
+            )
+        });
+    }
+
+    match agents {
+        [] => Err(io::Error::new(io::ErrorKind::NotFound, "no running agents")),
+        [agent] => Ok(agent.clone()),
+        _ => pick_interactively(message, agents),
+    }
+}
+
+/// An entry in the interactive agent picker.
+#[derive(Clone)]
+enum PickerChoice {
+    Agent(Agent),
+    Diff,
+}
+
+impl Display for PickerChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PickerChoice::Agent(agent) => agent.fmt(f),
+            PickerChoice::Diff => write!(f, "Compare the identities of these agents"),
+        }
+    }
+}
+
+/// Ask the user to pick one of `agents`, with the agent in the caller's `SSH_AUTH_SOCK`
+/// preselected.
+///
+/// The picker also offers a comparison of the agents' identities, printed to stderr before asking
+/// again.
+pub fn pick_interactively(message: &str, agents: &[Agent]) -> io::Result<Agent> {
+    let mut choices: Vec<PickerChoice> = agents.iter().cloned().map(PickerChoice::Agent).collect();
+    choices.push(PickerChoice::Diff);
+    let mut cursor = agents.iter().position(|a| a.is_current).unwrap_or_default();
+
+    loop {
+        let choice = Select::new(message, choices.clone())
+            .with_starting_cursor(cursor)
+            .prompt()
+            .map_err(|e| io::Error::new(io::ErrorKind::Interrupted, e))?;
+        match choice {
+            PickerChoice::Agent(agent) => return Ok(agent),
+            PickerChoice::Diff => {
+                eprintln!("{}", diff_identities(agents));
+                cursor = agents.len();
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::config;
+
+use super::running_agents::resolve_agent_pids;
+use super::Agent;
+
+/// How a spawned `ssh-agent` process runs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnMode {
+    /// Fork into the background, like `ssh-agent -s`.
+    #[default]
+    Daemon,
+    /// Stay in the foreground as a child process (`-D`).
+    Foreground,
+    /// Stay in the foreground and write debug output to stderr (`-d`).
+    Debug,
+}
+
+/// Builder for starting new `ssh-agent` processes.
+#[derive(Debug, Default, Clone)]
+pub struct AgentSpawner {
+    socket_path: Option<PathBuf>,
+    lifetime: Option<String>,
+    mode: SpawnMode,
+}
+
+impl AgentSpawner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind the agent to `path` instead of a random path under `$TMPDIR` (`-a`).
+    pub fn socket_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.socket_path = Some(path.into());
+        self
+    }
+
+    /// Set the default maximum lifetime of identities added to the agent (`-t`).
+    ///
+    /// The lifetime is in the `sshd_config(5)` time format, like `3600` or `1h30m`.
+    pub fn lifetime(mut self, lifetime: impl Into<String>) -> Self {
+        self.lifetime = Some(lifetime.into());
+        self
+    }
+
+    pub fn mode(mut self, mode: SpawnMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Start the agent and return it once its PID and socket are verified.
+    ///
+    /// Foreground and debug agents keep running after this returns, until they are killed, and are
+    /// reaped by a background thread.
+    pub fn spawn(&self) -> io::Result<Agent> {
+        let mut cmd = Command::new(&config::get().binaries.ssh_agent);
+        // force Bourne shell output so it can be parsed regardless of `$SHELL`
+        cmd.arg("-s");
+        match self.mode {
+            SpawnMode::Daemon => {}
+            SpawnMode::Foreground => {
+                cmd.arg("-D");
+            }
+            SpawnMode::Debug => {
+                cmd.arg("-d");
+            }
+        }
+        if let Some(path) = &self.socket_path {
+            cmd.arg("-a").arg(path);
+        }
+        if let Some(lifetime) = &self.lifetime {
+            cmd.arg("-t").arg(lifetime);
+        }
+
+        let mut child = cmd.stdin(Stdio::null()).stdout(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().expect("ssh-agent stdout is piped");
+
+        // daemonized agents print their environment and exit, while foreground agents print it and
+        // keep running, so only read as far as the pid
+        let mut socket_path = None;
+        let mut pid = None;
+        let mut lines = BufReader::new(stdout).lines();
+        for line in lines.by_ref() {
+            let line = line?;
+            if let Some(value) = line.strip_prefix("SSH_AUTH_SOCK=") {
+                socket_path = value.split(';').next().map(PathBuf::from);
+            } else if let Some(value) = line.strip_prefix("SSH_AGENT_PID=") {
+                pid = value.split(';').next().map(str::to_string);
+            } else if let Some(value) = line.strip_prefix("echo Agent pid ") {
+                pid = pid.or_else(|| value.split(';').next().map(str::to_string));
+                break;
+            }
+        }
+        if self.mode == SpawnMode::Daemon {
+            child.wait()?;
+        } else {
+            // keep reading so the agent's writes never fail, and reap it once it is killed so it
+            // doesn't linger as a zombie
+            thread::spawn(move || {
+                lines.for_each(drop);
+                let _ = child.wait();
+            });
+        }
+
+        let (Some(socket_path), Some(pid)) = (socket_path, pid) else {
+            return Err(io::Error::other(
+                "ssh-agent didn't report its socket and pid",
+            ));
+        };
+
+        let candidate = Agent {
+            pid,
+            socket_path,
+            ..Default::default()
+        };
+        resolve_agent_pids(std::slice::from_ref(&candidate))
+            .into_iter()
+            .find(|a| a.pid == candidate.pid)
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "ssh-agent pid {} isn't listening on {}",
+                    candidate.pid,
+                    candidate.socket_path.display()
+                ))
+            })
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::publish::write_atomically;
+
+use super::Agent;
+
+/// The states read for this run, or why they couldn't be read.
+static CACHED: Mutex<Option<Result<Arc<AgentStates>, String>>> = Mutex::new(None);
+
+/// What Agency remembers about agents between runs, stored as JSON in its `$XDG_STATE_HOME`
+/// directory.
+///
+/// Agents are told apart by their socket path and the start time of their process, so a new agent
+/// reusing an old socket path doesn't inherit anything.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentStates {
+    agents: Vec<AgentRecord>,
+}
+
+/// What Agency remembers about one agent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentRecord {
+    pub socket_path: PathBuf,
+    /// When the agent's process started, in clock ticks since boot.
+    pub start_time: u64,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Whether Agency locked the agent and hasn't unlocked it since.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub locked: bool,
+}
+
+impl AgentRecord {
+    /// Whether there is anything worth remembering.
+    fn is_empty(&self) -> bool {
+        !self.pinned && self.label.is_none() && !self.locked
+    }
+}
+
+impl AgentStates {
+    pub fn path() -> PathBuf {
+        config::state_dir().join("agents.json")
+    }
+
+    /// Read the stored states, or none if nothing was stored yet.
+    pub fn load() -> io::Result<Self> {
+        match fs::read_to_string(Self::path()) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The stored states, read once per run and shared by every check that needs them, or why
+    /// they couldn't be read.
+    pub fn cached() -> Result<Arc<Self>, String> {
+        let mut cached = CACHED.lock().unwrap_or_else(|e| e.into_inner());
+        cached
+            .get_or_insert_with(|| {
+                Self::load()
+                    .map(Arc::new)
+                    .map_err(|e| format!("unable to read {}: {}", Self::path().display(), e))
+            })
+            .clone()
+    }
+
+    /// Forget the states read by `cached`, so the next call reads them again.
+    pub fn forget_cached() {
+        *CACHED.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// Atomically store the states, dropping the records with nothing left in them and the ones
+    /// for agents whose socket is gone.
+    pub fn save(&mut self) -> io::Result<()> {
+        self.agents
+            .retain(|r| !r.is_empty() && r.socket_path.exists());
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomically(&Self::path(), &format!("{}\n", contents))
+    }
+
+    /// The record for `agent`, if there is one.
+    pub fn get(&self, agent: &Agent) -> Option<&AgentRecord> {
+        let start_time = agent.start_time()?;
+        self.agents
+            .iter()
+            .find(|r| r.socket_path == agent.socket_path && r.start_time == start_time)
+    }
+
+    /// The record for `agent`, created if there isn't one yet.
+    pub fn get_mut(&mut self, agent: &Agent) -> io::Result<&mut AgentRecord> {
+        let start_time = agent.start_time().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unable to find when agent pid {} started", agent.pid),
+            )
+        })?;
+        let position = self
+            .agents
+            .iter()
+            .position(|r| r.socket_path == agent.socket_path && r.start_time == start_time);
+        let index = match position {
+            Some(index) => index,
+            None => {
+                self.agents.push(AgentRecord {
+                    socket_path: agent.socket_path.clone(),
+                    start_time,
+                    pinned: false,
+                    label: None,
+                    locked: false,
+                });
+                self.agents.len() - 1
+            }
+        };
+        Ok(&mut self.agents[index])
+    }
+
+    /// Label `agent` with `label`, taking it from any other agent, or remove its label if `label`
+    /// is `None`.
+    pub fn set_label(&mut self, agent: &Agent, label: Option<String>) -> io::Result<()> {
+        if label.is_some() {
+            for record in &mut self.agents {
+                if record.label == label {
+                    record.label = None;
+                }
+            }
+        }
+        self.get_mut(agent)?.label = label;
+        Ok(())
+    }
+}
+
+impl Agent {
+    /// When the agent's process started, in clock ticks since boot, from `/proc/<pid>/stat`.
+    pub fn start_time(&self) -> Option<u64> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", self.pid)).ok()?;
+        // the command name can hold spaces and parentheses, so count fields after the last `)`
+        let (_, fields) = stat.rsplit_once(')')?;
+        fields.split_whitespace().nth(19)?.parse().ok()
+    }
+
+    /// Whether the agent was pinned with `ssh-agency pin`, or why that can't be told.
+    pub fn pinned(&self) -> Result<bool, String> {
+        Ok(AgentStates::cached()?.get(self).is_some_and(|r| r.pinned))
+    }
+
+    /// Whether the agent is known to be pinned with `ssh-agency pin`.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned().unwrap_or_default()
+    }
+
+    /// Whether Agency locked the agent and hasn't unlocked it since.
+    ///
+    /// The agent protocol has no request telling whether an agent is locked, and the ways of
+    /// finding out by trying change the agent, so Agency only knows about the locks it made.
+    pub fn is_locked_by_agency(&self) -> bool {
+        AgentStates::cached()
+            .ok()
+            .and_then(|states| states.get(self).map(|r| r.locked))
+            .unwrap_or_default()
+    }
+
+    /// Remember that Agency locked the agent, or that it unlocked it without `locked`.
+    ///
+    /// Only listings need to know, so failing to remember is reported rather than returned.
+    pub fn remember_lock(&self, locked: bool) {
+        let result = AgentStates::load().and_then(|mut states| {
+            states.get_mut(self)?.locked = locked;
+            states.save()
+        });
+        AgentStates::forget_cached();
+        if let Err(e) = result {
+            eprintln!(
+                "Unable to remember the lock on agent pid {}: {}",
+                self.pid, e
+            );
+        }
+    }
+
+    /// The label given to the agent with `ssh-agency label`, if any.
+    pub fn label(&self) -> Option<String> {
+        AgentStates::cached()
+            .ok()
+            .and_then(|states| states.get(self).and_then(|r| r.label.clone()))
+    }
+}
@@ -0,0 +1,22 @@
+use std::env;
+use std::path::PathBuf;
+
+use super::Agent;
+
+/// The name of the user unit running the systemd-managed agent.
+pub const AGENT_UNIT: &str = "ssh-agency-agent.service";
+
+/// Where the systemd-managed agent listens: `ssh-agency/systemd-agent.sock` in
+/// `$XDG_RUNTIME_DIR`, which is `%t` in user units. There is none without `XDG_RUNTIME_DIR`.
+pub fn systemd_agent_socket() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|d| !d.is_empty())
+        .map(|d| PathBuf::from(d).join("ssh-agency/systemd-agent.sock"))
+}
+
+impl Agent {
+    /// Whether this is the agent run by the `ssh-agency setup systemd` unit.
+    pub fn is_systemd_managed(&self) -> bool {
+        systemd_agent_socket().is_some_and(|path| path == self.socket_path)
+    }
+}
@@ -1,11 +1,48 @@
-use clap::{Args, Parser};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::agent::idle::IdleAction;
+use crate::agent::running_agents::ReductionStrategy;
+use crate::agent::selector::{AgentSelector, KeyQuery};
+use crate::config::{parse_duration, Config};
+use crate::shell::ShellFormat;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
     #[command(flatten)]
     pub reducers: Reducers,
 
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Read the configuration from PATH instead of ~/.config/ssh-agency/config.toml"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "Shell syntax for printed environment commands [default: sh]"
+    )]
+    pub shell: Option<ShellFormat>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Keep the stable socket link pointing at the picked agent and print it as SSH_AUTH_SOCK"
+    )]
+    pub stable_socket: bool,
+
     #[arg(short, long, help = "Show the currently running agents")]
     pub show_agents: bool,
 
@@ -16,6 +53,34 @@ pub struct Cli {
     )]
     pub ez: bool,
 
+    #[arg(
+        long,
+        value_enum,
+        help = "What ez mode should do when more than one agent is running [default: fail]"
+    )]
+    pub ez_policy: Option<EzPolicy>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "How ez mode picks or reduces agents under the `pick` and `reduce` policies [default: count]"
+    )]
+    pub ez_strategy: Option<ReductionStrategy>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Socket path for an agent started by ez mode"
+    )]
+    pub agent_socket: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "LIFE",
+        help = "Default identity lifetime for an agent started by ez mode, like `3600` or `1h30m`"
+    )]
+    pub agent_lifetime: Option<String>,
+
     #[arg(
         short,
         long = "purge",
@@ -24,6 +89,378 @@ pub struct Cli {
     pub purge_empty_agents: bool,
 }
 
+impl Cli {
+    /// Apply the options given on the command line over the `config`.
+    pub fn apply_overrides(&self, config: &mut Config) {
+        if let Some(shell) = self.shell {
+            config.output.shell = shell;
+        }
+        if let Some(policy) = self.ez_policy {
+            config.ez.policy = policy;
+        }
+        if let Some(strategy) = self.ez_strategy {
+            config.reduce.strategy = strategy;
+        }
+        if self.stable_socket {
+            config.stable_socket.enabled = true;
+            config.stable_socket.export = true;
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Add identity files to an agent
+    Add {
+        #[command(flatten)]
+        target: AgentTarget,
+
+        #[arg(
+            short = 't',
+            long,
+            value_name = "LIFE",
+            value_parser = parse_lifetime,
+            help = "Remove the identities after LIFE, like `3600` or `1h30m`"
+        )]
+        lifetime: Option<Duration>,
+
+        #[arg(
+            short,
+            long,
+            help = "Require confirmation for each use of the identities"
+        )]
+        confirm: bool,
+
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// Remove identities from an agent by fingerprint or key file
+    Remove {
+        #[command(flatten)]
+        target: AgentTarget,
+
+        #[arg(
+            required = true,
+            value_name = "KEY",
+            help = "A `SHA256:` fingerprint or key file"
+        )]
+        keys: Vec<String>,
+    },
+    /// Remove all identities from an agent
+    Clear {
+        #[command(flatten)]
+        target: AgentTarget,
+    },
+    /// Lock agents with a passphrase, hiding their identities until they are unlocked
+    Lock {
+        #[command(flatten)]
+        target: AgentsTarget,
+
+        #[arg(
+            long,
+            help = "Read the passphrase from the first line of stdin instead of prompting"
+        )]
+        passphrase_stdin: bool,
+    },
+    /// Unlock agents locked with a passphrase
+    Unlock {
+        #[command(flatten)]
+        target: AgentsTarget,
+
+        #[arg(
+            long,
+            help = "Read the passphrase from the first line of stdin instead of prompting"
+        )]
+        passphrase_stdin: bool,
+    },
+    /// Print the environment commands for an agent
+    Env {
+        #[arg(
+            short,
+            long,
+            value_name = "AGENT",
+            conflicts_with = "with_key",
+            help = "The agent to use: `current`, a PID, a socket path or a label; asks if omitted"
+        )]
+        agent: Option<AgentSelector>,
+
+        #[arg(
+            long,
+            value_name = "KEY",
+            help = "Use the first agent holding KEY: a `SHA256:` fingerprint, key file or comment substring"
+        )]
+        with_key: Option<KeyQuery>,
+    },
+    /// List the agents holding an identity
+    WhichAgent {
+        #[arg(help = "A `SHA256:` fingerprint, key file or comment substring")]
+        key: KeyQuery,
+
+        #[arg(
+            long,
+            help = "Print the environment commands for the first agent instead of listing them"
+        )]
+        env: bool,
+    },
+    /// Check that agents can sign with their identities
+    Check {
+        #[command(flatten)]
+        target: AgentsTarget,
+
+        #[arg(
+            short,
+            long,
+            value_name = "KEY",
+            help = "Only check KEY: a `SHA256:` fingerprint, key file or comment substring"
+        )]
+        key: Option<KeyQuery>,
+    },
+    /// Print an agent's public keys in `authorized_keys` format, or write them to `.pub` files
+    Export {
+        #[command(flatten)]
+        target: AgentTarget,
+
+        #[arg(
+            short,
+            long = "key",
+            value_name = "KEY",
+            help = "Only export KEY: a `SHA256:` fingerprint, key file or comment substring"
+        )]
+        keys: Vec<KeyQuery>,
+
+        #[arg(
+            short,
+            long,
+            value_name = "DIR",
+            conflicts_with_all = ["from", "restrict"],
+            help = "Write each key to its own `.pub` file in DIR instead"
+        )]
+        dir: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "PATTERNS",
+            value_parser = parse_host_patterns,
+            help = "Add a `from=\"PATTERNS\"` option limiting the hosts the keys are accepted from"
+        )]
+        from: Option<String>,
+
+        #[arg(
+            long,
+            help = "Add the `restrict` option, disabling forwarding and PTY allocation"
+        )]
+        restrict: bool,
+    },
+    /// Compare the identities of agents, listing the keys they share and the keys unique to each
+    Diff {
+        #[arg(
+            value_name = "AGENT",
+            help = "Agents to compare: `current`, PIDs, socket paths or labels; every running agent if omitted"
+        )]
+        agents: Vec<AgentSelector>,
+    },
+    /// Show the certificates loaded in agents, with their principals and validity
+    Certs {
+        #[arg(
+            short,
+            long,
+            value_name = "AGENT",
+            help = "Only show the certificates of AGENT: `current`, a PID, a socket path or a label"
+        )]
+        agent: Option<AgentSelector>,
+    },
+    /// Remove identities that are no longer useful from every agent
+    #[command(group(ArgGroup::new("prune").required(true).multiple(true)))]
+    Prune {
+        #[arg(long, group = "prune", help = "Remove expired certificates")]
+        expired_certs: bool,
+
+        #[arg(
+            long,
+            group = "prune",
+            value_name = "TIME",
+            value_parser = parse_lifetime,
+            help = "Act on agents holding identities that nobody has used for TIME, like `8h`"
+        )]
+        idle: Option<Duration>,
+
+        #[arg(
+            long,
+            value_enum,
+            requires = "idle",
+            help = "What to do with idle agents [default: `idle.action` from the configuration]"
+        )]
+        idle_action: Option<IdleAction>,
+
+        #[arg(
+            long,
+            requires = "idle",
+            help = "Read the passphrase to lock idle agents with from stdin instead of asking"
+        )]
+        passphrase_stdin: bool,
+    },
+    /// Show which agents hold the public keys in the configured key directories
+    Inventory {
+        #[arg(long, help = "Print the inventory as JSON")]
+        json: bool,
+    },
+    /// Run a command with the environment set for an agent
+    Exec {
+        #[arg(
+            value_name = "AGENT",
+            help = "The agent to use: `current`, a PID, a socket path or a label; asks if omitted"
+        )]
+        agent: Option<AgentSelector>,
+
+        #[arg(
+            last = true,
+            required = true,
+            value_name = "COMMAND",
+            help = "The command to run and its arguments, after `--`"
+        )]
+        command: Vec<OsString>,
+    },
+    /// Keep agents tidy in the background, applying the `[daemon]` policies whenever agents change
+    Daemon {
+        #[arg(long, help = "Apply the policies once and exit")]
+        once: bool,
+    },
+    /// Pin an agent, protecting it from purges, reductions and the daemon for as long as it runs
+    Pin {
+        #[arg(
+            value_name = "AGENT",
+            help = "The agent to pin: `current`, a PID, a socket path or a label; asks if omitted"
+        )]
+        agent: Option<AgentSelector>,
+    },
+    /// Unpin an agent pinned with `pin`
+    Unpin {
+        #[arg(
+            value_name = "AGENT",
+            help = "The agent to unpin: `current`, a PID, a socket path or a label; asks if omitted"
+        )]
+        agent: Option<AgentSelector>,
+    },
+    /// Label an agent, so it can be told apart in listings and selected by the label
+    Label {
+        #[arg(
+            value_name = "AGENT",
+            help = "The agent to label: `current`, a PID, a socket path or a label"
+        )]
+        agent: AgentSelector,
+
+        #[arg(
+            value_name = "LABEL",
+            required_unless_present = "remove",
+            value_parser = parse_label,
+            help = "The label, which moves from any agent that already has it"
+        )]
+        label: Option<String>,
+
+        #[arg(long, conflicts_with = "label", help = "Remove the agent's label")]
+        remove: bool,
+    },
+    /// Set Agency up to work with other tools
+    Setup {
+        #[command(subcommand)]
+        command: SetupCommands,
+    },
+    /// Start `$SHELL` with the environment set for an agent
+    Shell {
+        #[arg(
+            value_name = "AGENT",
+            help = "The agent to use: `current`, a PID, a socket path or a label; asks if omitted"
+        )]
+        agent: Option<AgentSelector>,
+    },
+}
+
+/// The agent a command operates on.
+#[derive(Args)]
+pub struct AgentTarget {
+    #[arg(
+        short,
+        long,
+        value_name = "AGENT",
+        help = "The agent to use: `current`, a PID, a socket path or a label; asks if omitted"
+    )]
+    pub agent: Option<AgentSelector>,
+}
+
+/// The agent a command operates on, or every agent it applies to.
+#[derive(Args)]
+pub struct AgentsTarget {
+    #[arg(
+        short,
+        long,
+        value_name = "AGENT",
+        conflicts_with = "all",
+        help = "The agent to use: `current`, a PID, a socket path or a label; asks if omitted"
+    )]
+    pub agent: Option<AgentSelector>,
+
+    #[arg(long, help = "Use every running agent")]
+    pub all: bool,
+}
+
+fn parse_label(s: &str) -> Result<String, String> {
+    match s.parse::<AgentSelector>() {
+        Ok(AgentSelector::Label(label)) => Ok(label),
+        _ => Err(format!(
+            "`{}` would be read as `current`, a PID or a socket path",
+            s
+        )),
+    }
+}
+
+/// A comma-separated list of host name or address patterns, which can't break out of the
+/// quoted `from="..."` option it ends up in.
+fn parse_host_patterns(s: &str) -> Result<String, String> {
+    let valid = |pattern: &str| {
+        !pattern.is_empty()
+            && pattern
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".-_:*?!/%".contains(c))
+    };
+    if s.split(',').all(valid) {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "`{}` is not a comma-separated list of host or address patterns",
+            s.escape_debug()
+        ))
+    }
+}
+
+fn parse_lifetime(s: &str) -> Result<Duration, String> {
+    parse_duration(s).ok_or_else(|| format!("`{}` is not a time like `3600` or `1h30m`", s))
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the effective configuration, merged from the defaults, the configuration file and
+    /// the command line
+    Show,
+}
+
+#[derive(Subcommand)]
+pub enum SetupCommands {
+    /// Install a systemd user unit running an agent at a fixed socket in `$XDG_RUNTIME_DIR`, which
+    /// reducers and purges leave alone
+    Systemd {
+        #[arg(long, help = "Install a unit running `ssh-agency daemon` instead")]
+        daemon: bool,
+
+        #[arg(long, help = "Only write the unit, without enabling and starting it")]
+        no_enable: bool,
+    },
+}
+
 #[derive(Args)]
 #[group(required = false, multiple = false)]
 pub struct Reducers {
@@ -41,3 +478,18 @@ pub struct Reducers {
     )]
     pub reduce_simple: bool,
 }
+
+/// The ways ez mode can resolve more than one running agent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EzPolicy {
+    /// Give up without picking an agent.
+    #[default]
+    Fail,
+    /// Reuse the agent in `$SSH_AUTH_SOCK` if it is one of the running agents.
+    Current,
+    /// Pick an agent with the reduction strategy, leaving the others running.
+    Pick,
+    /// Reduce to one agent with the reduction strategy and reuse the survivor.
+    Reduce,
+}
@@ -0,0 +1,46 @@
+use std::io;
+
+use crate::agent::selector::AgentSelector;
+use crate::agent::Agent;
+
+/// Print the certificates held by the selected agent, or by every agent without a selector.
+pub fn certs(agents: &[Agent], selector: Option<&AgentSelector>) -> io::Result<()> {
+    let agents: Vec<&Agent> = agents
+        .iter()
+        .filter(|a| selector.is_none_or(|s| s.matches(a)))
+        .collect();
+    if let (Some(selector), []) = (selector, &agents[..]) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no running agent matches `{}`", selector),
+        ));
+    }
+
+    let mut found = false;
+    for agent in agents {
+        let certificates = match agent.certificates() {
+            Ok(certificates) => certificates,
+            Err(e) => {
+                eprintln!(
+                    "Unable to list identities of agent pid {}: {}",
+                    agent.pid, e
+                );
+                continue;
+            }
+        };
+        if certificates.is_empty() {
+            continue;
+        }
+
+        found = true;
+        println!("PID {} at {}:", agent.pid, agent.socket_path.display());
+        for certificate in certificates {
+            println!("  {}", certificate);
+        }
+    }
+    if !found {
+        println!("No certificates loaded");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::io;
+
+use crate::agent::identities::AgentIdentityStatus;
+use crate::agent::selector::{choose_agent, KeyQuery};
+use crate::agent::Agent;
+use crate::cli::AgentsTarget;
+
+use super::failed_count;
+
+/// Have the selected agents sign a random challenge with each identity, or only the identities
+/// named by `key`, and verify the signatures.
+///
+/// A locked agent, or one without the identities to check, counts as a failure. With `--all` and
+/// a `key`, only the agents holding it are checked.
+pub fn check(agents: &[Agent], target: &AgentsTarget, key: Option<&KeyQuery>) -> io::Result<()> {
+    let targets = if target.all {
+        agents.to_vec()
+    } else {
+        vec![choose_agent(
+            target.agent.as_ref(),
+            agents,
+            "Pick an agent to check",
+        )?]
+    };
+    let only_holders = target.all && key.is_some();
+
+    let mut failures = 0;
+    let mut checked = 0;
+    for agent in &targets {
+        let header = || println!("PID {} at {}:", agent.pid, agent.socket_path.display());
+        if let Ok(AgentIdentityStatus::Locked) = agent.check_agent_identities() {
+            header();
+            println!("  FAIL agent is locked");
+            failures += 1;
+            checked += 1;
+            continue;
+        }
+
+        let identities: Vec<_> = match agent.identities() {
+            Ok(identities) => identities
+                .into_iter()
+                .filter(|i| key.is_none_or(|k| k.matches(i)))
+                .collect(),
+            Err(e) => {
+                header();
+                println!("  FAIL unable to list identities: {}", e);
+                failures += 1;
+                checked += 1;
+                continue;
+            }
+        };
+        if identities.is_empty() && only_holders {
+            continue;
+        }
+        header();
+        checked += 1;
+        if identities.is_empty() {
+            println!("  FAIL no identities to check");
+            failures += 1;
+        }
+
+        for identity in identities {
+            let name = format!("{} {}", identity.fingerprint(), identity.comment);
+            match agent.verify_signing(&identity) {
+                Ok(latency) => println!("  PASS {} ({}ms)", name, latency.as_millis()),
+                Err(e) => {
+                    println!("  FAIL {}: {}", name, e);
+                    failures += 1;
+                }
+            }
+        }
+    }
+    if checked == 0 && only_holders {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no agent holds the identities to check",
+        ));
+    }
+
+    failed_count(failures, "checks failed")
+}
@@ -0,0 +1,55 @@
+use std::io;
+
+use crate::agent::selector::{choose_agent, AgentSelector, KeyQuery};
+use crate::agent::Agent;
+use crate::use_agent;
+
+/// Print the environment commands for the selected agent, or the first agent holding `with_key`.
+pub fn env(
+    agents: &[Agent],
+    selector: Option<&AgentSelector>,
+    with_key: Option<&KeyQuery>,
+) -> io::Result<()> {
+    let agent = match with_key {
+        Some(key) => first_holder(agents, key)?,
+        None => choose_agent(
+            selector,
+            agents,
+            "Pick an agent to print the environment for",
+        )?,
+    };
+    use_agent(&agent);
+    Ok(())
+}
+
+/// List the agents holding the identity named by `key`, or print the environment commands for the
+/// first of them with `env`.
+pub fn which_agent(agents: &[Agent], key: &KeyQuery, env: bool) -> io::Result<()> {
+    if env {
+        use_agent(&first_holder(agents, key)?);
+        return Ok(());
+    }
+
+    let holders = key.holders(agents);
+    if holders.is_empty() {
+        return Err(not_held(key));
+    }
+    for agent in holders {
+        println!("{}", agent);
+    }
+    Ok(())
+}
+
+fn first_holder(agents: &[Agent], key: &KeyQuery) -> io::Result<Agent> {
+    key.holders(agents)
+        .into_iter()
+        .next()
+        .ok_or_else(|| not_held(key))
+}
+
+fn not_held(key: &KeyQuery) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no running agent holds {}", key),
+    )
+}
@@ -0,0 +1,48 @@
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use crate::agent::selector::{choose_agent, AgentSelector};
+use crate::agent::Agent;
+use crate::publish::stable_socket;
+
+/// Replace Agency with `command`, run with the environment set for the selected agent.
+///
+/// Only returns if the command can't be started.
+pub fn exec(
+    agents: &[Agent],
+    selector: Option<&AgentSelector>,
+    command: &[OsString],
+) -> io::Result<()> {
+    let agent = choose_agent(selector, agents, "Pick an agent to run the command with")?;
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no command given"))?;
+    Err(with_agent_env(Command::new(program), &agent)
+        .args(args)
+        .exec())
+}
+
+/// Replace Agency with `$SHELL`, or `/bin/sh` without it, run with the environment set for the
+/// selected agent.
+pub fn shell(agents: &[Agent], selector: Option<&AgentSelector>) -> io::Result<()> {
+    let agent = choose_agent(selector, agents, "Pick an agent to start a shell with")?;
+    let shell = env::var_os("SHELL")
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| OsString::from("/bin/sh"));
+    eprintln!(
+        "Starting {} with agent PID {}, exit it to return",
+        shell.to_string_lossy(),
+        agent.pid
+    );
+    Err(with_agent_env(Command::new(shell), &agent).exec())
+}
+
+fn with_agent_env(mut command: Command, agent: &Agent) -> Command {
+    command
+        .env("SSH_AUTH_SOCK", stable_socket::exported_path(agent))
+        .env("SSH_AGENT_PID", &agent.pid);
+    command
+}
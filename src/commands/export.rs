@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use ssh_key::{HashAlg, PublicKey};
+
+use crate::agent::selector::{choose_agent, AgentSelector, KeyQuery};
+use crate::agent::Agent;
+
+use super::failed_count;
+
+/// The `authorized_keys` options to put in front of exported keys.
+#[derive(Debug, Default, Clone)]
+pub struct KeyOptions {
+    pub from: Option<String>,
+    pub restrict: bool,
+}
+
+impl KeyOptions {
+    /// The options as an `authorized_keys` option list, or `None` without any options.
+    fn to_option_list(&self) -> Option<String> {
+        let mut options = vec![];
+        if self.restrict {
+            options.push("restrict".to_string());
+        }
+        if let Some(from) = &self.from {
+            options.push(format!("from=\"{}\"", from));
+        }
+        (!options.is_empty()).then(|| options.join(","))
+    }
+}
+
+/// Export the public keys of the selected agent's identities, or only the ones matching `keys`.
+///
+/// Certificates are exported as the plain key they certify, and each key is exported once.
+/// Without a `dir` the keys are printed as `authorized_keys` lines with the `options`.
+pub fn export(
+    agents: &[Agent],
+    selector: Option<&AgentSelector>,
+    keys: &[KeyQuery],
+    dir: Option<&Path>,
+    options: &KeyOptions,
+) -> io::Result<()> {
+    let agent = choose_agent(selector, agents, "Pick an agent to export keys from")?;
+
+    let mut seen = HashSet::new();
+    let public_keys: Vec<PublicKey> = agent
+        .identities()?
+        .iter()
+        .filter(|i| keys.is_empty() || keys.iter().any(|k| k.matches(i)))
+        .filter_map(|i| i.public_key())
+        .filter(|k| seen.insert(k.fingerprint(HashAlg::Sha256).to_string()))
+        .map(without_control_characters)
+        .collect();
+    if public_keys.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("agent pid {} has no matching identities", agent.pid),
+        ));
+    }
+
+    match dir {
+        Some(dir) => write_key_files(dir, &public_keys),
+        None => {
+            for key in &public_keys {
+                let line = key.to_openssh().map_err(io::Error::other)?;
+                match options.to_option_list() {
+                    Some(options) => println!("{} {}", options, line),
+                    None => println!("{}", line),
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// `key` with the control characters in its comment replaced by `?`.
+///
+/// Comments come from the agent, which may be a forwarded one, so a newline in one must not add a
+/// line of its own to `authorized_keys`.
+fn without_control_characters(mut key: PublicKey) -> PublicKey {
+    if key.comment().chars().any(char::is_control) {
+        let comment: String = key
+            .comment()
+            .chars()
+            .map(|c| if c.is_control() { '?' } else { c })
+            .collect();
+        key.set_comment(comment);
+    }
+    key
+}
+
+/// Write each of the `public_keys` to a `.pub` file in `dir` named after its comment.
+///
+/// Existing files are left alone.
+fn write_key_files(dir: &Path, public_keys: &[PublicKey]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let mut failures = 0;
+    let mut names = HashSet::new();
+    for key in public_keys {
+        let stem = file_stem(key);
+        let mut name = stem.clone();
+        let mut n = 1;
+        while !names.insert(name.clone()) {
+            n += 1;
+            name = format!("{}-{}", stem, n);
+        }
+
+        let path = dir.join(format!("{}.pub", name));
+        let line = key.to_openssh().map_err(io::Error::other)?;
+        let result = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .and_then(|mut f| writeln!(f, "{}", line));
+        match result {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(e) => {
+                eprintln!("Unable to write {}: {}", path.display(), e);
+                failures += 1;
+            }
+        }
+    }
+
+    failed_count(failures, "keys could not be written")
+}
+
+/// A file name for `key` from its comment, or its fingerprint without a comment.
+fn file_stem(key: &PublicKey) -> String {
+    let source = if key.comment().is_empty() {
+        key.fingerprint(HashAlg::Sha256).to_string()
+    } else {
+        key.comment().to_string()
+    };
+    source
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::agent::diff::diff_identities;
+use crate::agent::identities::key_file_fingerprint;
+use crate::agent::protocol::Constraints;
+use crate::agent::selector::{choose_agent, AgentSelector};
+use crate::agent::Agent;
+
+use super::failed_count;
+
+/// Add the private key `files` to the selected agent.
+///
+/// Every file is attempted even if an earlier one fails.
+pub fn add(
+    agents: &[Agent],
+    selector: Option<&AgentSelector>,
+    files: &[PathBuf],
+    constraints: Constraints,
+) -> io::Result<()> {
+    let agent = choose_agent(selector, agents, "Pick an agent to add identities to")?;
+
+    let mut failures = 0;
+    for file in files {
+        match agent.add_identity_file(file, constraints) {
+            Ok(fingerprint) => println!("Identity added: {} ({})", file.display(), fingerprint),
+            Err(e) => {
+                eprintln!("Unable to add {}: {}", file.display(), e);
+                failures += 1;
+            }
+        }
+    }
+
+    failed_count(failures, "identities could not be added")
+}
+
+/// Remove the identities named by `keys` from the selected agent.
+///
+/// Each key is either a `SHA256:` fingerprint or a public or private key file.
+pub fn remove(
+    agents: &[Agent],
+    selector: Option<&AgentSelector>,
+    keys: &[String],
+) -> io::Result<()> {
+    let agent = choose_agent(selector, agents, "Pick an agent to remove identities from")?;
+
+    let mut failures = 0;
+    for key in keys {
+        let fingerprint = if key.starts_with("SHA256:") {
+            Some(key.clone())
+        } else {
+            key_file_fingerprint(Path::new(key))
+        };
+
+        let result = match fingerprint {
+            Some(fingerprint) => agent.remove_identities_by_fingerprint(&fingerprint),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a fingerprint or readable key file",
+            )),
+        };
+        match result {
+            Ok(0) => {
+                eprintln!("{} is not loaded in agent pid {}", key, agent.pid);
+                failures += 1;
+            }
+            Ok(_) => println!("Identity removed: {}", key),
+            Err(e) => {
+                eprintln!("Unable to remove {}: {}", key, e);
+                failures += 1;
+            }
+        }
+    }
+
+    failed_count(failures, "identities could not be removed")
+}
+
+/// Remove all identities from the selected agent.
+pub fn clear(agents: &[Agent], selector: Option<&AgentSelector>) -> io::Result<()> {
+    let agent = choose_agent(
+        selector,
+        agents,
+        "Pick an agent to remove all identities from",
+    )?;
+    agent.remove_all_identities()?;
+    println!("All identities removed from agent pid {}", agent.pid);
+    Ok(())
+}
+
+/// Print the identities the `selectors` agents share and the identities unique to each.
+///
+/// Every running agent is compared when no agents are selected.
+pub fn diff(agents: &[Agent], selectors: &[AgentSelector]) -> io::Result<()> {
+    let mut compared: Vec<Agent> = vec![];
+    for selector in selectors {
+        let agent = selector.select(agents).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no running agent matches `{}`", selector),
+            )
+        })?;
+        if !compared.contains(&agent) {
+            compared.push(agent);
+        }
+    }
+    if selectors.is_empty() {
+        compared = agents.to_vec();
+    }
+
+    if compared.len() < 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least two agents are needed to compare",
+        ));
+    }
+    print!("{}", diff_identities(&compared));
+    Ok(())
+}
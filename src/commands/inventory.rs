@@ -0,0 +1,48 @@
+use std::io;
+
+use crate::agent::Agent;
+use crate::inventory::{scan_key_files, take_inventory, Placement};
+
+/// Print which of the running `agents` hold each key file in the configured key directories.
+pub fn inventory(agents: &[Agent], json: bool) -> io::Result<()> {
+    let inventory = take_inventory(scan_key_files()?, agents);
+
+    if json {
+        let json = serde_json::to_string_pretty(&inventory).map_err(io::Error::other)?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    if inventory.is_empty() {
+        println!("No public keys found in the key directories");
+        return Ok(());
+    }
+
+    println!(
+        "{:<10}  {:<50}  {:<12}  KEY",
+        "LOADED", "FINGERPRINT", "AGENTS"
+    );
+    for entry in &inventory {
+        let placement = match entry.placement {
+            Placement::Nowhere => "nowhere",
+            Placement::Once => "once",
+            Placement::Duplicated => "duplicated",
+        };
+        let pids = if entry.agents.is_empty() {
+            "-".to_string()
+        } else {
+            let pids: Vec<&str> = entry.agents.iter().map(|a| a.pid.as_str()).collect();
+            pids.join(",")
+        };
+        println!(
+            "{:<10}  {:<50}  {:<12}  {} ({})",
+            placement,
+            entry.key.fingerprint,
+            pids,
+            entry.key.path.display(),
+            entry.key.comment,
+        );
+    }
+
+    Ok(())
+}
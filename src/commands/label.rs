@@ -0,0 +1,19 @@
+use std::io;
+
+use crate::agent::selector::{choose_agent, AgentSelector};
+use crate::agent::state::AgentStates;
+use crate::agent::Agent;
+
+/// Give the selected agent `label`, or remove its label if `label` is `None`.
+pub fn label(agents: &[Agent], selector: &AgentSelector, label: Option<String>) -> io::Result<()> {
+    let agent = choose_agent(Some(selector), agents, "Pick an agent to label")?;
+
+    let mut states = AgentStates::load()?;
+    states.set_label(&agent, label.clone())?;
+    states.save()?;
+    match label {
+        Some(label) => println!("Agent pid {} labelled {}", agent.pid, label),
+        None => println!("Agent pid {} unlabelled", agent.pid),
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::io;
+
+use crate::agent::identities::{ask_passphrase, AgentIdentityStatus};
+use crate::agent::selector::choose_agent;
+use crate::agent::Agent;
+use crate::cli::AgentsTarget;
+
+use super::failed_count;
+
+/// Lock the selected agent, or every unlocked agent with `--all`, with one passphrase.
+///
+/// Locking every agent skips the ones that are already locked, so it can be run repeatedly from a
+/// screen locker hook.
+pub fn lock(agents: &[Agent], target: &AgentsTarget, passphrase_stdin: bool) -> io::Result<()> {
+    let targets = if target.all {
+        agents.iter().filter(|a| !is_locked(a)).cloned().collect()
+    } else {
+        vec![choose_agent(
+            target.agent.as_ref(),
+            agents,
+            "Pick an agent to lock",
+        )?]
+    };
+    if targets.is_empty() {
+        println!("No agents to lock");
+        return Ok(());
+    }
+
+    let passphrase = read_passphrase("Passphrase to lock the agent with:", true, passphrase_stdin)?;
+    let mut failures = 0;
+    for agent in &targets {
+        match agent.client().and_then(|mut c| c.lock(&passphrase)) {
+            Ok(()) => {
+                println!("Agent pid {} locked", agent.pid);
+                agent.remember_lock(true);
+            }
+            Err(e) => {
+                eprintln!("Unable to lock agent pid {}: {}", agent.pid, e);
+                failures += 1;
+            }
+        }
+    }
+
+    failed_count(failures, "agents could not be locked")
+}
+
+/// Unlock the selected agent, or every locked agent with `--all`, with one passphrase.
+pub fn unlock(agents: &[Agent], target: &AgentsTarget, passphrase_stdin: bool) -> io::Result<()> {
+    let targets = if target.all {
+        agents.iter().filter(|a| is_locked(a)).cloned().collect()
+    } else {
+        vec![choose_agent(
+            target.agent.as_ref(),
+            agents,
+            "Pick an agent to unlock",
+        )?]
+    };
+    if targets.is_empty() {
+        println!("No locked agents");
+        return Ok(());
+    }
+
+    let passphrase = read_passphrase("Passphrase to unlock the agent:", false, passphrase_stdin)?;
+    let mut failures = 0;
+    for agent in &targets {
+        match agent.client().and_then(|mut c| c.unlock(&passphrase)) {
+            Ok(()) => {
+                println!("Agent pid {} unlocked", agent.pid);
+                agent.remember_lock(false);
+            }
+            Err(e) => {
+                eprintln!("Unable to unlock agent pid {}: {}", agent.pid, e);
+                failures += 1;
+            }
+        }
+    }
+
+    failed_count(failures, "agents could not be unlocked")
+}
+
+fn is_locked(agent: &Agent) -> bool {
+    matches!(
+        agent.check_agent_identities(),
+        Ok(AgentIdentityStatus::Locked)
+    )
+}
+
+/// Read the passphrase from stdin, or ask for it.
+pub(super) fn read_passphrase(prompt: &str, confirm: bool, from_stdin: bool) -> io::Result<String> {
+    if !from_stdin {
+        return ask_passphrase(prompt, confirm);
+    }
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
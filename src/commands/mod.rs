@@ -0,0 +1,107 @@
+pub mod certificates;
+pub mod check;
+pub mod env;
+pub mod exec;
+pub mod export;
+pub mod identities;
+pub mod inventory;
+pub mod label;
+pub mod lock;
+pub mod pin;
+pub mod prune;
+pub mod setup;
+
+use std::io;
+
+use crate::agent::protocol::Constraints;
+use crate::agent::Agent;
+use crate::cli::Commands;
+use crate::config;
+
+/// Run a subcommand against the running `agents`.
+pub fn run(command: &Commands, agents: &[Agent]) -> io::Result<()> {
+    match command {
+        // handled before agents are discovered
+        Commands::Config { .. } | Commands::Daemon { .. } | Commands::Setup { .. } => Ok(()),
+        Commands::Add {
+            target,
+            lifetime,
+            confirm,
+            files,
+        } => {
+            let constraints = Constraints {
+                lifetime: *lifetime,
+                confirm: *confirm,
+            };
+            identities::add(agents, target.agent.as_ref(), files, constraints)
+        }
+        Commands::Remove { target, keys } => {
+            identities::remove(agents, target.agent.as_ref(), keys)
+        }
+        Commands::Clear { target } => identities::clear(agents, target.agent.as_ref()),
+        Commands::Lock {
+            target,
+            passphrase_stdin,
+        } => lock::lock(agents, target, *passphrase_stdin),
+        Commands::Unlock {
+            target,
+            passphrase_stdin,
+        } => lock::unlock(agents, target, *passphrase_stdin),
+        Commands::Env { agent, with_key } => env::env(agents, agent.as_ref(), with_key.as_ref()),
+        Commands::WhichAgent { key, env } => env::which_agent(agents, key, *env),
+        Commands::Check { target, key } => check::check(agents, target, key.as_ref()),
+        Commands::Export {
+            target,
+            keys,
+            dir,
+            from,
+            restrict,
+        } => {
+            let options = export::KeyOptions {
+                from: from.clone(),
+                restrict: *restrict,
+            };
+            export::export(
+                agents,
+                target.agent.as_ref(),
+                keys,
+                dir.as_deref(),
+                &options,
+            )
+        }
+        Commands::Diff { agents: selectors } => identities::diff(agents, selectors),
+        Commands::Certs { agent } => certificates::certs(agents, agent.as_ref()),
+        Commands::Prune {
+            expired_certs,
+            idle,
+            idle_action,
+            passphrase_stdin,
+        } => {
+            let idle = idle.map(|threshold| prune::IdleOptions {
+                threshold,
+                action: idle_action.unwrap_or(config::get().idle.action),
+                passphrase_stdin: *passphrase_stdin,
+            });
+            prune::prune(agents, *expired_certs, idle.as_ref())
+        }
+        Commands::Inventory { json } => inventory::inventory(agents, *json),
+        Commands::Exec { agent, command } => exec::exec(agents, agent.as_ref(), command),
+        Commands::Shell { agent } => exec::shell(agents, agent.as_ref()),
+        Commands::Pin { agent } => pin::pin(agents, agent.as_ref(), true),
+        Commands::Unpin { agent } => pin::pin(agents, agent.as_ref(), false),
+        Commands::Label {
+            agent,
+            label,
+            remove: _,
+        } => label::label(agents, agent, label.clone()),
+    }
+}
+
+/// Turn a count of `failures` into an error saying `failures` `message`.
+fn failed_count(failures: usize, message: &str) -> io::Result<()> {
+    if failures == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} {}", failures, message)))
+    }
+}
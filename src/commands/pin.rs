@@ -0,0 +1,25 @@
+use std::io;
+
+use crate::agent::selector::{choose_agent, AgentSelector};
+use crate::agent::state::AgentStates;
+use crate::agent::Agent;
+
+/// Pin the selected agent, or unpin it without `pinned`.
+pub fn pin(agents: &[Agent], selector: Option<&AgentSelector>, pinned: bool) -> io::Result<()> {
+    let message = if pinned {
+        "Pick an agent to pin"
+    } else {
+        "Pick an agent to unpin"
+    };
+    let agent = choose_agent(selector, agents, message)?;
+
+    let mut states = AgentStates::load()?;
+    states.get_mut(&agent)?.pinned = pinned;
+    states.save()?;
+    println!(
+        "Agent pid {} {}",
+        agent.pid,
+        if pinned { "pinned" } else { "unpinned" }
+    );
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::io;
+use std::time::Duration;
+
+use crate::agent::idle::{apply_idle_policy, IdleAction};
+use crate::agent::Agent;
+
+use super::failed_count;
+use super::lock::read_passphrase;
+
+/// What `prune --idle` does with idle agents.
+pub struct IdleOptions {
+    pub threshold: Duration,
+    pub action: IdleAction,
+    pub passphrase_stdin: bool,
+}
+
+/// Remove the identities picked out by the prune options from every agent.
+pub fn prune(agents: &[Agent], expired_certs: bool, idle: Option<&IdleOptions>) -> io::Result<()> {
+    if expired_certs {
+        prune_expired_certificates(agents)?;
+    }
+    if let Some(idle) = idle {
+        prune_idle_agents(agents, idle)?;
+    }
+    Ok(())
+}
+
+fn prune_idle_agents(agents: &[Agent], options: &IdleOptions) -> io::Result<()> {
+    let passphrase = match options.action {
+        IdleAction::Lock => Some(read_passphrase(
+            "Passphrase to lock idle agents with:",
+            true,
+            options.passphrase_stdin,
+        )?),
+        _ => None,
+    };
+    let (_, failures) = apply_idle_policy(
+        agents.to_vec(),
+        options.threshold,
+        options.action,
+        passphrase.as_deref(),
+    );
+    failed_count(failures, "idle agents could not be pruned")
+}
+
+fn prune_expired_certificates(agents: &[Agent]) -> io::Result<()> {
+    let mut failures = 0;
+    for agent in agents {
+        match agent.remove_expired_certificates() {
+            Ok(removed) => {
+                for certificate in removed {
+                    println!(
+                        "Removed expired certificate {} ({}) from agent pid {}",
+                        certificate.key_id,
+                        certificate.identity.fingerprint(),
+                        agent.pid
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!(
+                    "Unable to remove expired certificates from agent pid {}: {}",
+                    agent.pid, e
+                );
+                failures += 1;
+            }
+        }
+    }
+
+    failed_count(failures, "agents could not be pruned")
+}
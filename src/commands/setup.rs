@@ -0,0 +1,163 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::agent::systemd::{systemd_agent_socket, AGENT_UNIT};
+use crate::config;
+
+/// The name of the user unit running `ssh-agency daemon`.
+const DAEMON_UNIT: &str = "ssh-agency-daemon.service";
+
+/// Install a systemd user unit running an agent at the fixed systemd agent socket, or
+/// `ssh-agency daemon` with `daemon`, and enable and start it unless `no_enable` is set.
+///
+/// The daemon unit reads the configuration at `config_path`.
+pub fn systemd(daemon: bool, no_enable: bool, config_path: Option<&Path>) -> io::Result<()> {
+    let (name, unit) = if daemon {
+        (DAEMON_UNIT, daemon_unit(config_path)?)
+    } else {
+        (AGENT_UNIT, agent_unit()?)
+    };
+
+    let path = user_unit_dir()?.join(name);
+    fs::create_dir_all(path.parent().unwrap_or(Path::new("/")))?;
+    fs::write(&path, unit)?;
+    eprintln!("Wrote {}", path.display());
+
+    if no_enable {
+        eprintln!("Start it with: systemctl --user enable --now {}", name);
+    } else {
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", name])?;
+        eprintln!("Enabled and started {}", name);
+    }
+    if !daemon {
+        if let Some(socket) = systemd_agent_socket() {
+            eprintln!("Its socket is {}", socket.display());
+        }
+    }
+    Ok(())
+}
+
+fn agent_unit() -> io::Result<String> {
+    if systemd_agent_socket().is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "XDG_RUNTIME_DIR isn't set, so there is no fixed place for the agent socket",
+        ));
+    }
+    let ssh_agent = find_executable(&config::get().binaries.ssh_agent)?;
+    // `rm` isn't in /bin everywhere, like on NixOS
+    let rm = find_executable(Path::new("rm"))?;
+    Ok(format!(
+        "[Unit]
+Description=SSH agent managed by ssh-agency
+
+[Service]
+Type=simple
+RuntimeDirectory=ssh-agency
+RuntimeDirectoryPreserve=yes
+ExecStartPre={} -f %t/ssh-agency/systemd-agent.sock
+ExecStart={} -D -a %t/ssh-agency/systemd-agent.sock
+
+[Install]
+WantedBy=default.target
+",
+        quote(&rm)?,
+        quote(&ssh_agent)?
+    ))
+}
+
+fn daemon_unit(config_path: Option<&Path>) -> io::Result<String> {
+    let exe = env::current_exe()?;
+    let config_arg = match config_path {
+        Some(path) if path.exists() => format!(" --config {}", quote(&path.canonicalize()?)?),
+        _ => String::new(),
+    };
+    Ok(format!(
+        "[Unit]
+Description=ssh-agency daemon keeping SSH agents tidy
+
+[Service]
+Type=simple
+ExecStart={}{} daemon
+Restart=on-failure
+
+[Install]
+WantedBy=default.target
+",
+        quote(&exe)?,
+        config_arg
+    ))
+}
+
+/// `path` as one quoted `ExecStart=` argument, with the characters systemd would otherwise treat
+/// as escapes, specifiers or variables escaped.
+fn quote(path: &Path) -> io::Result<String> {
+    let path = path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} isn't valid UTF-8", path.display()),
+        )
+    })?;
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Ok(quoted)
+}
+
+/// `systemd/user` in `$XDG_CONFIG_HOME`, or in `~/.config` without it.
+fn user_unit_dir() -> io::Result<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|config_home| config_home.join("systemd/user"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME isn't set"))
+}
+
+/// The absolute path of `program`, searched for in `$PATH` if it is a bare name, since units need
+/// absolute paths.
+fn find_executable(program: &Path) -> io::Result<PathBuf> {
+    if program.components().count() > 1 {
+        return program.canonicalize();
+    }
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} isn't in PATH", program.display()),
+            )
+        })
+}
+
+fn systemctl(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "systemctl --user {} failed",
+            args.join(" ")
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,545 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent::idle::IdleAction;
+use crate::agent::running_agents::ReductionStrategy;
+use crate::cli::EzPolicy;
+use crate::shell::ShellFormat;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The user's configuration, read from `~/.config/ssh-agency/config.toml`.
+///
+/// Every section and setting is optional and falls back to the behavior Agency has without a
+/// configuration file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discovery: DiscoveryConfig,
+    pub binaries: BinariesConfig,
+    pub output: OutputConfig,
+    pub ez: EzConfig,
+    pub reduce: ReduceConfig,
+    pub protect: ProtectConfig,
+    pub safety: SafetyConfig,
+    pub health: HealthConfig,
+    pub stable_socket: StableSocketConfig,
+    pub env_files: EnvFilesConfig,
+    pub ssh_config: SshConfigConfig,
+    pub tmux: TmuxConfig,
+    pub inventory: InventoryConfig,
+    pub idle: IdleConfig,
+    pub daemon: DaemonConfig,
+    /// Identities to load into new or empty agents.
+    pub keys: Vec<KeyConfig>,
+}
+
+/// Where to look for agent sockets.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Directories holding `agent.*` sockets or `ssh-*` directories with them, or agent sockets.
+    pub paths: Vec<PathBuf>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            paths: vec![env::temp_dir()],
+        }
+    }
+}
+
+/// The OpenSSH tools Agency runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BinariesConfig {
+    pub ssh_agent: PathBuf,
+    pub tmux: PathBuf,
+}
+
+impl Default for BinariesConfig {
+    fn default() -> Self {
+        Self {
+            ssh_agent: PathBuf::from("ssh-agent"),
+            tmux: PathBuf::from("tmux"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// The shell syntax for printed environment commands.
+    pub shell: ShellFormat,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EzConfig {
+    /// What ez mode does when more than one agent is running.
+    pub policy: EzPolicy,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReduceConfig {
+    /// The strategy used when Agency picks or reduces agents on its own, like in ez mode.
+    pub strategy: ReductionStrategy,
+}
+
+/// Agents that are never killed by purges or reductions.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtectConfig {
+    /// Glob patterns matched against agent socket paths.
+    pub sockets: Vec<String>,
+    /// `SHA256:` fingerprints of keys whose holders are protected.
+    pub fingerprints: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafetyConfig {
+    /// Remove the sockets of dead agents on every run.
+    pub clean_dead_sockets: bool,
+    /// Never kill the agent in the caller's `SSH_AUTH_SOCK`.
+    pub protect_current: bool,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            clean_dead_sockets: true,
+            protect_current: false,
+        }
+    }
+}
+
+/// How long Agency waits on agents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Milliseconds to wait for an agent to answer before considering it hung.
+    pub timeout_ms: u64,
+    /// Milliseconds after which an agent answering is considered slow.
+    pub slow_ms: u64,
+    /// How many agents are probed at once.
+    pub workers: usize,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 3000,
+            slow_ms: 500,
+            workers: 8,
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.max(1))
+    }
+
+    pub fn slow(&self) -> Duration {
+        Duration::from_millis(self.slow_ms)
+    }
+}
+
+/// A symlink Agency keeps pointing at the agent it last picked.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StableSocketConfig {
+    pub enabled: bool,
+    /// The link path; `$XDG_RUNTIME_DIR/ssh-agency/agent.sock`, or `~/.ssh/agent.sock` without
+    /// `XDG_RUNTIME_DIR`, by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Print the link path as `SSH_AUTH_SOCK` instead of the agent's own socket.
+    pub export: bool,
+}
+
+impl StableSocketConfig {
+    /// The link path, with a leading `~` expanded to the home directory.
+    pub fn path(&self) -> PathBuf {
+        match &self.path {
+            Some(path) => expand_home(path),
+            None => match env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+                Some(runtime_dir) => Path::new(&runtime_dir).join("ssh-agency/agent.sock"),
+                None => expand_home(Path::new("~/.ssh/agent.sock")),
+            },
+        }
+    }
+}
+
+/// Files with the environment for the agent Agency last picked, for programs that don't run
+/// Agency themselves.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvFilesConfig {
+    pub enabled: bool,
+    /// The directory for the files; `ssh-agency` in `$XDG_STATE_HOME` (`~/.local/state` by
+    /// default) if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+impl EnvFilesConfig {
+    /// The directory for the files, with a leading `~` expanded to the home directory.
+    pub fn dir(&self) -> PathBuf {
+        match &self.dir {
+            Some(dir) => expand_home(dir),
+            None => state_dir(),
+        }
+    }
+}
+
+/// An ssh_config include setting `IdentityAgent` to the agent Agency last picked, so `ssh` finds
+/// it without any environment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfigConfig {
+    pub enabled: bool,
+    /// The include path; `~/.ssh/config.d/agency.conf` if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// The `Host` patterns the agent is used for.
+    pub hosts: Vec<String>,
+}
+
+impl Default for SshConfigConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            hosts: vec!["*".to_string()],
+        }
+    }
+}
+
+impl SshConfigConfig {
+    /// The include path, with a leading `~` expanded to the home directory.
+    pub fn path(&self) -> PathBuf {
+        expand_home(
+            self.path
+                .as_deref()
+                .unwrap_or(Path::new("~/.ssh/config.d/agency.conf")),
+        )
+    }
+}
+
+/// Pushing the environment for the agent Agency last picked into the running tmux server.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TmuxConfig {
+    pub enabled: bool,
+}
+
+/// What happens to agents holding identities that nobody has used for a while.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// How long an agent may go unused, in the `sshd_config(5)` time format; the daemon leaves
+    /// idle agents alone if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    pub action: IdleAction,
+    /// A file whose first line is the passphrase the daemon locks idle agents with. A relative
+    /// path is taken from systemd's `$CREDENTIALS_DIRECTORY`, for `LoadCredential=`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase_file: Option<PathBuf>,
+}
+
+impl IdleConfig {
+    /// The idle timeout as a duration, if it is set.
+    ///
+    /// The timeout is validated when the configuration is loaded, so an invalid one never turns
+    /// the policy off.
+    pub fn timeout_duration(&self) -> Option<Duration> {
+        self.timeout.as_deref().and_then(parse_duration)
+    }
+
+    /// The passphrase file, with a leading `~` expanded or a relative path taken from
+    /// `$CREDENTIALS_DIRECTORY`, if it is set.
+    pub fn passphrase_path(&self) -> Option<PathBuf> {
+        let path = expand_home(self.passphrase_file.as_deref()?);
+        match env::var_os("CREDENTIALS_DIRECTORY").filter(|d| !d.is_empty()) {
+            Some(credentials) if path.is_relative() => Some(Path::new(&credentials).join(path)),
+            _ => Some(path),
+        }
+    }
+}
+
+/// What `ssh-agency daemon` does on every pass, and how often it makes one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// The time between passes, in the `sshd_config(5)` time format.
+    pub interval: String,
+    /// Also make a pass when the discovery directories change, on Linux.
+    pub watch: bool,
+    /// Remove the sockets of dead agents.
+    pub prune_dead: bool,
+    /// Kill empty or hung agents that no process has as its `SSH_AUTH_SOCK`.
+    pub prune_empty_orphans: bool,
+    /// Kill the agents ranked lowest by `reduce.strategy` beyond this many.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_agents: Option<usize>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            interval: "5m".to_string(),
+            watch: true,
+            prune_dead: true,
+            prune_empty_orphans: false,
+            max_agents: None,
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// The time between passes.
+    ///
+    /// The interval is validated when the configuration is loaded, so the five minute fallback is
+    /// only for configurations built in code.
+    pub fn interval(&self) -> Duration {
+        parse_duration(&self.interval)
+            .filter(|d| !d.is_zero())
+            .unwrap_or(Duration::from_secs(300))
+    }
+}
+
+/// Where to look for key files when taking an inventory of keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InventoryConfig {
+    /// Directories searched for `*.pub` public key files.
+    pub key_dirs: Vec<PathBuf>,
+}
+
+impl Default for InventoryConfig {
+    fn default() -> Self {
+        Self {
+            key_dirs: vec![PathBuf::from("~/.ssh")],
+        }
+    }
+}
+
+/// An identity file to load into new or empty agents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyConfig {
+    pub path: PathBuf,
+    /// The maximum lifetime of the identity, in the `sshd_config(5)` time format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifetime: Option<String>,
+    /// Require confirmation from the user each time the identity is used.
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+impl KeyConfig {
+    /// The identity file path, with a leading `~` expanded to the home directory.
+    pub fn expanded_path(&self) -> PathBuf {
+        expand_home(&self.path)
+    }
+
+    /// The lifetime as a duration, if it is set, or an error if it isn't valid.
+    ///
+    /// Lifetimes are also validated when the configuration is loaded, since loading a key without
+    /// the lifetime it was configured with must never happen silently.
+    pub fn lifetime_duration(&self) -> io::Result<Option<Duration>> {
+        match self.lifetime.as_deref() {
+            None => Ok(None),
+            Some(lifetime) => parse_duration(lifetime).map(Some).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid lifetime {:?}", lifetime),
+                )
+            }),
+        }
+    }
+}
+
+/// Parse a duration in the `sshd_config(5)` time format, like `3600`, `90m` or `1h30m`.
+///
+/// A number without a unit is in seconds. Durations too long to count in seconds aren't valid.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let multiplier = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let seconds = number.parse::<u64>().ok()?.checked_mul(multiplier)?;
+        total = total.checked_add(seconds)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total = total.checked_add(number.parse::<u64>().ok()?)?;
+    }
+
+    (!s.trim().is_empty()).then_some(Duration::from_secs(total))
+}
+
+/// Format `duration` in the `sshd_config(5)` time format with its two largest units, like `1h30m`.
+pub fn format_duration(duration: Duration) -> String {
+    const UNITS: [(u64, char); 5] = [
+        (604800, 'w'),
+        (86400, 'd'),
+        (3600, 'h'),
+        (60, 'm'),
+        (1, 's'),
+    ];
+    let mut remaining = duration.as_secs();
+    let parts: Vec<String> = UNITS
+        .iter()
+        .filter_map(|&(seconds, unit)| {
+            let count = remaining / seconds;
+            remaining %= seconds;
+            (count > 0).then(|| format!("{}{}", count, unit))
+        })
+        .take(2)
+        .collect();
+
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.concat()
+    }
+}
+
+/// Agency's directory in `$XDG_STATE_HOME`, or in `~/.local/state` without it.
+pub fn state_dir() -> PathBuf {
+    match env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        Some(state_home) => Path::new(&state_home).join("ssh-agency"),
+        None => expand_home(Path::new("~/.local/state/ssh-agency")),
+    }
+}
+
+/// Expand a leading `~` in `path` to the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl Config {
+    /// The configuration file to read: `$SSH_AGENCY_CONFIG` if set, or `config.toml` in the
+    /// `ssh-agency` directory of `$XDG_CONFIG_HOME` (`~/.config` by default).
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = Config::env_path() {
+            return Some(path);
+        }
+
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_home.join("ssh-agency").join("config.toml"))
+    }
+
+    /// The configuration file given with `$SSH_AGENCY_CONFIG`, if it is set.
+    pub fn env_path() -> Option<PathBuf> {
+        env::var_os("SSH_AGENCY_CONFIG")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Read the configuration at `path`, which has to exist.
+    pub fn load(path: &Path) -> io::Result<Config> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid configuration in {}: {}", path.display(), e),
+            )
+        };
+        let contents = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("unable to read the configuration {}: {}", path.display(), e),
+            )
+        })?;
+        let config: Config = toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+        config.validate().map_err(invalid)?;
+        Ok(config)
+    }
+
+    /// Read the configuration at `path` like `load`, falling back to the defaults if it doesn't
+    /// exist.
+    pub fn load_or_default(path: &Path) -> io::Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        Config::load(path)
+    }
+
+    /// Check the settings that deserialize from any string but only make sense in some forms.
+    fn validate(&self) -> Result<(), String> {
+        if parse_duration(&self.daemon.interval).is_none_or(|d| d.is_zero()) {
+            return Err(format!(
+                "`daemon.interval` must be a non-zero time, not {:?}",
+                self.daemon.interval
+            ));
+        }
+        if let Some(timeout) = &self.idle.timeout {
+            if parse_duration(timeout).is_none_or(|d| d.is_zero()) {
+                return Err(format!(
+                    "`idle.timeout` must be a non-zero time, not {:?}",
+                    timeout
+                ));
+            }
+            if self.idle.action == IdleAction::Lock && self.idle.passphrase_file.is_none() {
+                return Err(
+                    "`idle.action = \"lock\"` with `idle.timeout` needs `idle.passphrase_file`, \
+                     since the daemon has nobody to ask for a passphrase"
+                        .to_string(),
+                );
+            }
+        }
+        for key in &self.keys {
+            if let Err(e) = key.lifetime_duration() {
+                return Err(format!("{} for the key {}", e, key.path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Render the configuration as TOML.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// Set the configuration used for the rest of the process.
+///
+/// Only the first call has any effect.
+pub fn init(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+/// The configuration for this process, or the defaults if `init` hasn't been called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
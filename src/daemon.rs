@@ -0,0 +1,163 @@
+//! `ssh-agency daemon`, which applies the `[daemon]` policies to the discovered agents on an
+//! interval and whenever the discovery directories change.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use inotify::{Inotify, WatchMask};
+
+use crate::agent::idle::{apply_idle_policy, IdleAction};
+use crate::agent::running_agents::{
+    clean_dead_agents, enforce_max_agents, get_current_agents, get_dead_agents, probe_agents,
+    purge_empty_orphans, resolve_agent_pids,
+};
+use crate::agent::state::AgentStates;
+use crate::config;
+
+/// How long to wait for a burst of changes to the discovery directories to settle, like an agent
+/// creating its directory and then its socket, before making a pass.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// Make a pass over the agents, then keep making them until killed unless `once` is set.
+pub fn run(once: bool) -> io::Result<()> {
+    // read once up front, since the file may be a credential only there while starting
+    let idle = &config::get().idle;
+    let passphrase = match (idle.timeout_duration(), idle.passphrase_path(), idle.action) {
+        (Some(_), Some(path), IdleAction::Lock) => Some(read_passphrase_file(&path)?),
+        _ => None,
+    };
+    let passphrase = passphrase.as_deref();
+    if once {
+        return pass(passphrase);
+    }
+
+    let daemon = &config::get().daemon;
+    let changes = if daemon.watch {
+        watch_discovery_paths()?
+    } else {
+        None
+    };
+    eprintln!(
+        "Tidying agents every {}{}",
+        config::format_duration(daemon.interval()),
+        if changes.is_some() {
+            " and when the discovery directories change"
+        } else {
+            ""
+        }
+    );
+
+    loop {
+        if let Err(e) = pass(passphrase) {
+            eprintln!("Unable to tidy agents: {}", e);
+        }
+        wait_for_next_pass(changes.as_ref(), daemon.interval());
+    }
+}
+
+/// Apply the configured policies to the agents running now, locking idle agents with
+/// `passphrase`.
+fn pass(passphrase: Option<&str>) -> io::Result<()> {
+    let config = config::get();
+    // pins and labels may have changed since the last pass
+    AgentStates::forget_cached();
+    let agents = get_current_agents()?;
+    let running_agents = resolve_agent_pids(&agents);
+    if config.daemon.prune_dead {
+        clean_dead_agents(&get_dead_agents(agents, running_agents.clone()));
+    }
+
+    let mut running_agents = probe_agents(running_agents);
+    if let Some(threshold) = config.idle.timeout_duration() {
+        (running_agents, _) =
+            apply_idle_policy(running_agents, threshold, config.idle.action, passphrase);
+    }
+    if config.daemon.prune_empty_orphans {
+        running_agents = purge_empty_orphans(running_agents);
+    }
+    if let Some(max) = config.daemon.max_agents {
+        enforce_max_agents(running_agents, max, config.reduce.strategy);
+    }
+    Ok(())
+}
+
+/// The first line of the passphrase file at `path`.
+fn read_passphrase_file(path: &Path) -> io::Result<String> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "unable to read the passphrase file {}: {}",
+                path.display(),
+                e
+            ),
+        )
+    })?;
+    match contents.lines().next() {
+        Some(passphrase) if !passphrase.is_empty() => Ok(passphrase.to_string()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the passphrase file {} is empty", path.display()),
+        )),
+    }
+}
+
+/// Sleep until `interval` passes or a change arrives on `changes`, and the changes settle.
+fn wait_for_next_pass(changes: Option<&Receiver<()>>, interval: Duration) {
+    let Some(changes) = changes else {
+        thread::sleep(interval);
+        return;
+    };
+
+    match changes.recv_timeout(interval) {
+        Ok(()) => {
+            thread::sleep(SETTLE_TIME);
+            while changes.try_recv().is_ok() {}
+        }
+        Err(RecvTimeoutError::Timeout) => {}
+        // the watcher stopped, so only the interval is left
+        Err(RecvTimeoutError::Disconnected) => thread::sleep(interval),
+    }
+}
+
+/// Watch the discovery directories for entries being created or removed, sending on the returned
+/// channel for every batch of changes.
+#[cfg(target_os = "linux")]
+fn watch_discovery_paths() -> io::Result<Option<Receiver<()>>> {
+    let mut inotify = Inotify::init()?;
+    for path in &config::get().discovery.paths {
+        match inotify.watches().add(
+            path,
+            WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_TO | WatchMask::MOVED_FROM,
+        ) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!("Not watching {}, which doesn't exist", path.display())
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while inotify.read_events_blocking(&mut buffer).is_ok() {
+            if sender.send(()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Some(receiver))
+}
+
+/// Watching needs inotify, so elsewhere the daemon only makes a pass every interval.
+#[cfg(not(target_os = "linux"))]
+fn watch_discovery_paths() -> io::Result<Option<Receiver<()>>> {
+    eprintln!("Warning: `daemon.watch` is only supported on Linux, so only the interval is used");
+    Ok(None)
+}
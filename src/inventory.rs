@@ -0,0 +1,118 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use ssh_key::{HashAlg, PublicKey};
+
+use crate::agent::Agent;
+use crate::config::{self, expand_home};
+
+/// A public key file found in one of the inventory's key directories.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyFile {
+    pub path: PathBuf,
+    pub fingerprint: String,
+    pub comment: String,
+}
+
+/// Where a key file's identity is loaded among the running agents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    Nowhere,
+    Once,
+    Duplicated,
+}
+
+/// An agent holding the identity of a key file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Holder {
+    pub pid: String,
+    pub socket_path: PathBuf,
+}
+
+/// A key file and the agents that hold its identity.
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryEntry {
+    #[serde(flatten)]
+    pub key: KeyFile,
+    pub placement: Placement,
+    pub agents: Vec<Holder>,
+}
+
+/// Read the public key files in the configured key directories.
+///
+/// Files that aren't OpenSSH public keys, like certificates, are skipped.
+pub fn scan_key_files() -> io::Result<Vec<KeyFile>> {
+    let mut keys = vec![];
+    for dir in &config::get().inventory.key_dirs {
+        let dir = expand_home(dir);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "pub"))
+            .collect();
+        paths.sort();
+        keys.extend(paths.iter().filter_map(|p| read_key_file(p)));
+    }
+
+    Ok(keys)
+}
+
+/// Read the public key file at `path`, if it is one.
+pub fn read_key_file(path: &Path) -> Option<KeyFile> {
+    let key = PublicKey::read_openssh_file(path).ok()?;
+    Some(KeyFile {
+        path: path.to_path_buf(),
+        fingerprint: key.fingerprint(HashAlg::Sha256).to_string(),
+        comment: key.comment().to_string(),
+    })
+}
+
+/// Cross-reference the `keys` with the identities of every agent in `agents`.
+///
+/// Agents that can't list their identities, like locked ones, hold nothing.
+pub fn take_inventory(keys: Vec<KeyFile>, agents: &[Agent]) -> Vec<InventoryEntry> {
+    let mut holders: HashMap<String, Vec<Holder>> = HashMap::new();
+    for agent in agents {
+        // a key and its certificate share a fingerprint, but the agent only holds it once
+        let fingerprints: BTreeSet<String> = agent
+            .identity_fingerprints()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        for fingerprint in fingerprints {
+            holders.entry(fingerprint).or_default().push(Holder {
+                pid: agent.pid.clone(),
+                socket_path: agent.socket_path.clone(),
+            });
+        }
+    }
+
+    let mut inventory: Vec<InventoryEntry> = keys
+        .into_iter()
+        .map(|key| {
+            let agents = holders.get(&key.fingerprint).cloned().unwrap_or_default();
+            let placement = match agents.len() {
+                0 => Placement::Nowhere,
+                1 => Placement::Once,
+                _ => Placement::Duplicated,
+            };
+            InventoryEntry {
+                key,
+                placement,
+                agents,
+            }
+        })
+        .collect();
+    inventory.sort_by(|a, b| (a.placement, &a.key.path).cmp(&(b.placement, &b.key.path)));
+    inventory
+}
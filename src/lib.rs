@@ -1,26 +1,93 @@
 pub mod agent;
 pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod daemon;
+pub mod inventory;
+pub mod publish;
+pub mod shell;
 
 use agent::{
+    current::{current_agent_status, CurrentAgentStatus},
+    identities::AgentIdentityStatus,
     running_agents::{
-        check_agents, get_current_agents, purge_empty_agents, resolve_agent_pids,
+        check_agents, pick_agent, purge_empty_agents, reduce_agents, ReductionStrategy,
         RunningAgentCheckStatus,
     },
+    selector::pick_interactively,
+    spawner::AgentSpawner,
     Agent,
 };
-use inquire::{Confirm, Select};
+use cli::EzPolicy;
+use inquire::Confirm;
 use std::io;
 
-pub fn basic_operation() -> io::Result<()> {
-    let agents: Vec<Agent> = get_current_agents()?;
-    let agents = resolve_agent_pids(&agents);
+/// Non-interactively guarantee an agent and print the environment for it.
+///
+/// Everything written to stdout here is meant to be `eval`'d by the calling shell, so failures are
+/// reported as `echo` commands. A live agent in the caller's `SSH_AUTH_SOCK` is preferred over
+/// starting or picking another one.
+pub fn ez_operation(
+    agents: Vec<Agent>,
+    policy: EzPolicy,
+    strategy: ReductionStrategy,
+    spawner: &AgentSpawner,
+) {
+    let current = current_agent_status(&agents);
+    let chosen = match &agents[..] {
+        [agent] => Some(agent.clone()),
+        [] => {
+            if let CurrentAgentStatus::Unmanaged(sock) = &current {
+                let shell = config::get().output.shell;
+                println!("{}", shell.export("SSH_AUTH_SOCK", &format!("{:?}", sock)));
+                return;
+            }
+
+            match spawner.spawn() {
+                Ok(agent) => Some(agent),
+                Err(e) => {
+                    println!("echo Failed to start new agent");
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+            }
+        }
+        _ => match policy {
+            EzPolicy::Fail => None,
+            EzPolicy::Current => current.agent().cloned(),
+            EzPolicy::Pick => pick_agent(&agents, strategy),
+            EzPolicy::Reduce => reduce_agents(agents, strategy),
+        },
+    };
+
+    match chosen {
+        Some(agent) => use_agent(&agent),
+        None => println!("echo Too many running agents"),
+    }
+}
+
+/// Prepare the agent Agency picked for the caller and print the environment for it.
+///
+/// An agent without identities gets the identities from the configuration loaded first, and the
+/// places Agency maintains outside the shell are pointed at it.
+pub fn use_agent(agent: &Agent) {
+    if let Ok(AgentIdentityStatus::NoIdentities) = agent.check_agent_identities() {
+        agent.load_configured_identities();
+    }
+    publish::publish(agent);
+    agent.print_env_commands();
+}
+
+/// Interactively tidy and pick from the running `agents`, already resolved and probed by the
+/// caller, and print the environment for the picked one.
+pub fn basic_operation(agents: Vec<Agent>) -> io::Result<()> {
     let agents = {
         if agents.len() > 1 {
             let message =
-            "Found multiple running agents, would you like to terminate all but 1 without identities?";
+            "Found multiple running agents, would you like to terminate hung agents and all but 1 without identities?";
             let response = Confirm::new(message)
                 .with_default(true)
-                .with_help_message("Terminates all but 1 empty agents by default")
+                .with_help_message("Terminates hung agents and all but 1 empty agents by default")
                 .prompt();
 
             match response {
@@ -44,14 +111,16 @@ pub fn basic_operation() -> io::Result<()> {
             // Print out a source-able string sequence eg:
             // export SSH_AUTH_SOCK=/tmp/ssh-Ojfuw4Y4n9Fm/agent.704
             // export SSH_AGENT_PID=705
-            agent.print_env_commands();
+            use_agent(&agent);
         }
         RunningAgentCheckStatus::MultipleAgents => {
-            let resp = Select::new("Multiple agents are running; you can pick an agent to print environment variables for", agents)
-                .prompt();
+            let resp = pick_interactively(
+                "Multiple agents are running; you can pick an agent to print environment variables for",
+                &agents,
+            );
             match resp {
                 Ok(choice) => {
-                    choice.print_env_commands();
+                    use_agent(&choice);
                 }
                 Err(e) => {
                     eprintln!("Failed to select agent: {}", e);
@@ -1,53 +1,95 @@
 use std::io;
-use std::process::Command;
+use std::process::ExitCode;
 
 use clap::Parser;
-use ssh_agency::agent::identities::AgentIdentityStatus;
+use ssh_agency::agent::current::current_agent_status;
 use ssh_agency::agent::running_agents::{
-    get_current_agents, get_dead_agents, purge_empty_agents, resolve_agent_pids,
+    clean_dead_agents, get_current_agents, get_dead_agents, probe_agents, purge_empty_agents,
+    reduce_agents, resolve_agent_pids, ReductionStrategy,
 };
+use ssh_agency::agent::spawner::AgentSpawner;
 use ssh_agency::agent::Agent;
-use ssh_agency::basic_operation;
-use ssh_agency::cli::Cli;
+use ssh_agency::cli::{Cli, Commands, ConfigCommands, SetupCommands};
+use ssh_agency::config::{self, Config};
+use ssh_agency::{basic_operation, commands, daemon, ez_operation, publish};
 
-fn main() -> io::Result<()> {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> io::Result<()> {
+    // a file given explicitly has to exist, unlike the one in the default place
+    let mut config = match cli.config.clone().or_else(Config::env_path) {
+        Some(path) => Config::load(&path)?,
+        None => match Config::default_path() {
+            Some(path) => Config::load_or_default(&path)?,
+            None => Config::default(),
+        },
+    };
+    cli.apply_overrides(&mut config);
+    config::init(config);
+    let config = config::get();
+
+    if let Some(Commands::Config {
+        command: ConfigCommands::Show,
+    }) = &cli.command
+    {
+        print!("{}", config.to_toml());
+        return Ok(());
+    }
+
+    if let Some(Commands::Setup {
+        command: SetupCommands::Systemd { daemon, no_enable },
+    }) = &cli.command
+    {
+        let config_path = cli.config.clone().or_else(Config::default_path);
+        return commands::setup::systemd(*daemon, *no_enable, config_path.as_deref());
+    }
+
+    // the daemon discovers agents itself on every pass
+    if let Some(Commands::Daemon { once }) = &cli.command {
+        return daemon::run(*once);
+    }
 
     let agents: Vec<Agent> = get_current_agents()?;
-    let mut running_agents = resolve_agent_pids(&agents);
-    let dead_agents = get_dead_agents(agents.clone(), running_agents.clone());
-
-    for a in dead_agents.iter() {
-        match a.clean_dead_agent_socket() {
-            Ok(()) => {
-                println!("Removed dead agent's socket: {}", &a.socket_path.display());
-            }
-            Err(e) => {
-                eprintln!(
-                    "Unable to remove socket for agent at {}: {}",
-                    &a.socket_path.display(),
-                    e,
-                );
-            }
-        }
+    let running_agents = resolve_agent_pids(&agents);
+    let dead_agents = if config.safety.clean_dead_sockets {
+        get_dead_agents(agents.clone(), running_agents.clone())
+    } else {
+        vec![]
+    };
+
+    clean_dead_agents(&dead_agents);
+
+    let running_agents = probe_agents(running_agents);
+    if let Some(warning) = current_agent_status(&running_agents).warning() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if let Some(command) = &cli.command {
+        return commands::run(command, &running_agents);
     }
 
     if cli.ez {
-        match &running_agents[..] {
-            [agent] => agent.print_env_commands(),
-            [] => {
-                // TODO: refactor into a function?
-                let start_agent_output = Command::new("ssh-agent").arg("-s").status();
-                if let Ok(s) = start_agent_output {
-                    if !s.success() {
-                        println!("echo Failed to start new agent");
-                    }
-                }
-            }
-            _ => {
-                println!("echo Too many running agents");
-            }
+        let mut spawner = AgentSpawner::new();
+        if let Some(path) = &cli.agent_socket {
+            spawner = spawner.socket_path(path);
+        }
+        if let Some(lifetime) = &cli.agent_lifetime {
+            spawner = spawner.lifetime(lifetime);
         }
+        ez_operation(
+            running_agents,
+            config.ez.policy,
+            config.reduce.strategy,
+            &spawner,
+        );
         return Ok(());
     }
 
@@ -73,36 +115,19 @@ fn main() -> io::Result<()> {
     }
 
     if reducers.reduce_simple {
-        let kill_queue: Vec<Agent> = running_agents.drain(1..).collect();
-        for mut a in kill_queue {
-            a.kill_agent();
+        if let Some(survivor) = reduce_agents(running_agents, ReductionStrategy::Simple) {
+            publish::publish(&survivor);
         }
         return Ok(());
     }
 
     if reducers.reduce_by_count {
-        running_agents.sort_unstable_by(|a, b| {
-            let a_identities = match a.check_agent_identities().unwrap_or_default() {
-                AgentIdentityStatus::NoIdentities => 0,
-                AgentIdentityStatus::Identities(c) => c,
-                AgentIdentityStatus::ConnectionRefused => -1,
-            };
-
-            let b_identities = match b.check_agent_identities().unwrap_or_default() {
-                AgentIdentityStatus::NoIdentities => 0,
-                AgentIdentityStatus::Identities(c) => c,
-                AgentIdentityStatus::ConnectionRefused => -1,
-            };
-
-            b_identities.cmp(&a_identities)
-        });
-        let kill_queue: Vec<Agent> = running_agents.drain(1..).collect();
-        for mut a in kill_queue {
-            a.kill_and_clean_agent();
+        if let Some(survivor) = reduce_agents(running_agents, ReductionStrategy::Count) {
+            publish::publish(&survivor);
         }
         return Ok(());
     }
 
-    basic_operation()?;
+    basic_operation(running_agents)?;
     Ok(())
 }
@@ -0,0 +1,66 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+use crate::agent::Agent;
+use crate::config;
+use crate::publish::{header, write_atomically};
+use crate::shell::ShellFormat;
+
+/// The env file for `shell`, like `~/.local/state/ssh-agency/myhost-sh`.
+pub fn env_file_path(shell: ShellFormat) -> PathBuf {
+    let name = shell
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default();
+    config::get()
+        .env_files
+        .dir()
+        .join(format!("{}-{}", hostname(), name))
+}
+
+/// Atomically write an env file for every shell syntax with the environment for `agent`, if env
+/// files are enabled.
+pub fn write(agent: &Agent) -> io::Result<()> {
+    if !config::get().env_files.enabled {
+        return Ok(());
+    }
+
+    for shell in ShellFormat::value_variants() {
+        let contents = format!("{}\n{}\n", header(agent), agent.env_commands(*shell));
+        write_atomically(&env_file_path(*shell), &contents)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the env files if they are for `agent`.
+pub fn remove_if_for(agent: &Agent) -> io::Result<()> {
+    if !config::get().env_files.enabled {
+        return Ok(());
+    }
+
+    let sh_file = env_file_path(ShellFormat::Sh);
+    if !fs::read_to_string(sh_file).is_ok_and(|c| c.lines().next() == Some(&header(agent))) {
+        return Ok(());
+    }
+
+    for shell in ShellFormat::value_variants() {
+        match fs::remove_file(env_file_path(*shell)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
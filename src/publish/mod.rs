@@ -0,0 +1,88 @@
+//! Places outside the calling shell that are kept pointing at the agent Agency picked, so
+//! programs that don't `eval` Agency's output can find it too.
+
+pub mod env_files;
+pub mod ssh_config;
+pub mod stable_socket;
+pub mod tmux;
+
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+
+use crate::agent::Agent;
+
+/// Point everything Agency maintains at `agent`, the agent it just picked.
+///
+/// Failures are reported on stderr without stopping the others from being updated.
+pub fn publish(agent: &Agent) {
+    if let Err(e) = stable_socket::repoint(agent) {
+        eprintln!("Unable to update the stable socket link: {}", e);
+    }
+    // after the link, since the files name it when it is exported
+    if let Err(e) = env_files::write(agent) {
+        eprintln!("Unable to write the env files: {}", e);
+    }
+    if let Err(e) = ssh_config::write(agent) {
+        eprintln!("Unable to write the ssh_config include: {}", e);
+    }
+    if let Err(e) = tmux::set_environment(agent) {
+        eprintln!("Unable to update the tmux environment: {}", e);
+    }
+}
+
+/// Withdraw everything Agency maintains that points at `agent`, which was killed or found dead.
+pub fn retract(agent: &Agent) {
+    if let Err(e) = stable_socket::remove_if_pointing_at(agent) {
+        eprintln!("Unable to remove the stable socket link: {}", e);
+    }
+    if let Err(e) = env_files::remove_if_for(agent) {
+        eprintln!("Unable to remove the env files: {}", e);
+    }
+    if let Err(e) = ssh_config::remove_if_for(agent) {
+        eprintln!("Unable to remove the ssh_config include: {}", e);
+    }
+    if let Err(e) = tmux::warn_about_stale_panes(agent) {
+        eprintln!("Unable to check the tmux panes: {}", e);
+    }
+}
+
+/// The first line of the files Agency generates for `agent`, naming its socket so they can be
+/// recognised once the agent is gone.
+pub(crate) fn header(agent: &Agent) -> String {
+    format!(
+        "# Generated by ssh-agency for the agent at {}; changes are overwritten.",
+        agent.socket_path.display()
+    )
+}
+
+/// Replace the file at `path` with `contents`, readable only by the user, creating its directory
+/// if needed. Files that already have the contents are left alone.
+pub(crate) fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    if fs::read_to_string(path).is_ok_and(|c| c == contents) {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+
+    // write next to the final path and rename over it, so readers never see a partial file
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .and_then(|_| fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
@@ -0,0 +1,38 @@
+use std::fs;
+use std::io;
+
+use crate::agent::Agent;
+use crate::config;
+use crate::publish::{header, stable_socket, write_atomically};
+
+/// Atomically write the ssh_config include pointing `IdentityAgent` at `agent` for the configured
+/// `Host` patterns, if the include is enabled.
+pub fn write(agent: &Agent) -> io::Result<()> {
+    let ssh_config = &config::get().ssh_config;
+    if !ssh_config.enabled {
+        return Ok(());
+    }
+
+    let socket_path = stable_socket::exported_path(agent);
+    let contents = format!(
+        "{}\nHost {}\n    IdentityAgent \"{}\"\n",
+        header(agent),
+        ssh_config.hosts.join(" "),
+        socket_path.display()
+    );
+    write_atomically(&ssh_config.path(), &contents)
+}
+
+/// Remove the ssh_config include if it is for `agent`.
+pub fn remove_if_for(agent: &Agent) -> io::Result<()> {
+    let ssh_config = &config::get().ssh_config;
+    if !ssh_config.enabled {
+        return Ok(());
+    }
+
+    let path = ssh_config.path();
+    if fs::read_to_string(&path).is_ok_and(|c| c.lines().next() == Some(&header(agent))) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
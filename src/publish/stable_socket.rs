@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, DirBuilderExt};
+use std::path::{Path, PathBuf};
+
+use crate::agent::Agent;
+use crate::config;
+
+/// The configured stable socket path, if the stable socket is enabled.
+pub fn stable_path() -> Option<PathBuf> {
+    let stable = &config::get().stable_socket;
+    stable.enabled.then(|| stable.path())
+}
+
+/// The path to print as `SSH_AUTH_SOCK` for `agent`: the stable socket if it is exported and
+/// points at the agent, or the agent's own socket otherwise.
+pub fn exported_path(agent: &Agent) -> PathBuf {
+    match stable_path() {
+        Some(path)
+            if config::get().stable_socket.export
+                && fs::read_link(&path).is_ok_and(|t| t == agent.socket_path) =>
+        {
+            path
+        }
+        _ => agent.socket_path.clone(),
+    }
+}
+
+/// Atomically point the stable socket link at `agent`'s socket, if the stable socket is enabled.
+pub fn repoint(agent: &Agent) -> io::Result<()> {
+    let Some(path) = stable_path() else {
+        return Ok(());
+    };
+    if fs::read_link(&path).is_ok_and(|t| t == agent.socket_path) {
+        return Ok(());
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    // link next to the final path and rename over it, so the link is never missing
+    let temporary = temporary_path(&path);
+    let _ = fs::remove_file(&temporary);
+    symlink(&agent.socket_path, &temporary)?;
+    fs::rename(&temporary, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+/// Remove the stable socket link if it points at `agent`'s socket.
+pub fn remove_if_pointing_at(agent: &Agent) -> io::Result<()> {
+    let Some(path) = stable_path() else {
+        return Ok(());
+    };
+    match fs::read_link(&path) {
+        Ok(target) if target == agent.socket_path => fs::remove_file(&path),
+        _ => Ok(()),
+    }
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::io;
+use std::process::{Command, Stdio};
+
+use crate::agent::Agent;
+use crate::config;
+use crate::publish::stable_socket;
+
+/// Set the environment for `agent` in the global tmux environment and in that of every session,
+/// if the tmux integration is enabled and a tmux server is running.
+///
+/// New panes pick it up right away; existing ones need `eval "$(tmux show-environment -s)"`.
+pub fn set_environment(agent: &Agent) -> io::Result<()> {
+    if !config::get().tmux.enabled {
+        return Ok(());
+    }
+    let Some(sessions) = sessions()? else {
+        return Ok(());
+    };
+
+    let socket_path = stable_socket::exported_path(agent);
+    let socket_path = socket_path.to_string_lossy();
+    let targets = std::iter::once(None).chain(sessions.iter().map(Some));
+    for target in targets {
+        for (name, value) in [
+            ("SSH_AUTH_SOCK", socket_path.as_ref()),
+            ("SSH_AGENT_PID", agent.pid.as_str()),
+        ] {
+            let scope = match target {
+                None => vec!["-g"],
+                Some(session) => vec!["-t", session.as_str()],
+            };
+            tmux(&[&["set-environment"], scope.as_slice(), &[name, value]].concat())?;
+        }
+    }
+    Ok(())
+}
+
+/// Warn about tmux sessions with panes whose environment still references `agent`, which was
+/// killed or found dead.
+pub fn warn_about_stale_panes(agent: &Agent) -> io::Result<()> {
+    if !config::get().tmux.enabled || sessions()?.is_none() {
+        return Ok(());
+    }
+
+    let panes = tmux(&["list-panes", "-a", "-F", "#{session_name}\t#{pane_pid}"])?;
+    let mut stale: Vec<(String, usize)> = vec![];
+    for (session, pane_pid) in panes.lines().filter_map(|l| l.split_once('\t')) {
+        if !pane_references(pane_pid, agent) {
+            continue;
+        }
+        match stale.iter_mut().find(|(s, _)| s == session) {
+            Some((_, count)) => *count += 1,
+            None => stale.push((session.to_string(), 1)),
+        }
+    }
+
+    for (session, count) in stale {
+        eprintln!(
+            "tmux session {} has {} {} still using killed agent PID {}; run `eval \"$(tmux show-environment -s)\"` in {}",
+            session,
+            count,
+            if count == 1 { "pane" } else { "panes" },
+            agent.pid,
+            if count == 1 { "it" } else { "them" }
+        );
+    }
+    Ok(())
+}
+
+/// Whether the environment of the process `pane_pid` points at `agent`.
+fn pane_references(pane_pid: &str, agent: &Agent) -> bool {
+    let Ok(environ) = fs::read(format!("/proc/{}/environ", pane_pid)) else {
+        return false;
+    };
+    let socket_line = format!("SSH_AUTH_SOCK={}", agent.socket_path.display());
+    let pid_line = format!("SSH_AGENT_PID={}", agent.pid);
+    environ
+        .split(|b| *b == 0)
+        .map(String::from_utf8_lossy)
+        .any(|var| var == socket_line || var == pid_line)
+}
+
+/// The names of the sessions on the tmux server, or `None` if no server is running or tmux isn't
+/// installed.
+fn sessions() -> io::Result<Option<Vec<String>>> {
+    let output = match Command::new(&config::get().binaries.tmux)
+        .args(["list-sessions", "-F", "#{session_name}"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+    ))
+}
+
+/// Run a tmux command, returning its output.
+fn tmux(args: &[&str]) -> io::Result<String> {
+    let output = Command::new(&config::get().binaries.tmux)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "tmux {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
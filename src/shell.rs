@@ -0,0 +1,35 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// The shell syntaxes environment commands can be printed in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellFormat {
+    /// Bourne-style shells like `sh`, `bash` and `zsh`.
+    #[default]
+    Sh,
+    /// `csh` and `tcsh`.
+    Csh,
+    /// The `fish` shell.
+    Fish,
+}
+
+impl ShellFormat {
+    /// A command setting and exporting the variable `name` to `value`.
+    pub fn export(&self, name: &str, value: &str) -> String {
+        match self {
+            ShellFormat::Sh => format!("export {}={}", name, value),
+            ShellFormat::Csh => format!("setenv {} {};", name, value),
+            ShellFormat::Fish => format!("set -gx {} {};", name, value),
+        }
+    }
+
+    /// A command removing the variable `name` from the environment.
+    pub fn unset(&self, name: &str) -> String {
+        match self {
+            ShellFormat::Sh => format!("unset {};", name),
+            ShellFormat::Csh => format!("unsetenv {};", name),
+            ShellFormat::Fish => format!("set -e {};", name),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+mod run_binary;
+use run_binary::{test_identity_path, Sandbox};
+
+#[test]
+fn autoload_into_empty_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(&format!(
+        "[[keys]]\npath = {:?}\nlifetime = \"1h\"\n",
+        test_identity_path()
+    ));
+    let agent = sandbox.make_agent();
+
+    let output = sandbox.run(&["-y"]);
+    assert!(output.contains(&agent.pid));
+    assert!(sandbox.run(&["-s"]).contains("1 identity"));
+
+    // the identity is already loaded, so it isn't added twice
+    sandbox.run(&["-y"]);
+    assert!(sandbox.run(&["-s"]).contains("1 identity"));
+}
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use ssh_agency::agent::Agent;
+mod run_binary;
+use run_binary::Sandbox;
+
+/// Generate a key in the sandbox with a certificate valid for `validity`, and load both into the
+/// `agent`.
+fn add_certified_key(sandbox: &Sandbox, agent: &Agent, name: &str, validity: &str) -> PathBuf {
+    let ca = sandbox.dir.join("ca");
+    if !ca.exists() {
+        keygen(&["-t", "ed25519", "-N", "", "-f", ca.to_str().unwrap()]);
+    }
+    let key = sandbox.dir.join(name);
+    keygen(&["-t", "ed25519", "-N", "", "-f", key.to_str().unwrap()]);
+    let public_key = key.with_extension("pub");
+    keygen(&[
+        "-s",
+        ca.to_str().unwrap(),
+        "-I",
+        name,
+        "-n",
+        "alice,bob",
+        "-V",
+        validity,
+        public_key.to_str().unwrap(),
+    ]);
+
+    Command::new("ssh-add")
+        .arg(&key)
+        .env("SSH_AUTH_SOCK", &agent.socket_path)
+        .stderr(Stdio::null())
+        .status()
+        .expect("Unable to add certificate");
+    key
+}
+
+fn keygen(args: &[&str]) {
+    let status = Command::new("ssh-keygen")
+        .arg("-q")
+        .args(args)
+        .stderr(Stdio::null())
+        .status()
+        .expect("Unable to run ssh-keygen");
+    assert!(status.success());
+}
+
+#[test]
+fn list_and_prune_certificates() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent();
+    add_certified_key(&sandbox, &agent, "expired", "20200101Z:20200102Z");
+    add_certified_key(&sandbox, &agent, "valid", "-1h:+1d");
+
+    let output = sandbox.run(&["-s"]);
+    assert!(output.contains("4 identities, 1 EXPIRED certificate"));
+
+    let output = sandbox.run(&["certs"]);
+    assert!(output.contains("expired (SHA256:"));
+    assert!(output.contains("for alice, bob"));
+    assert!(output.contains("until 2020-01-02 00:00 UTC, EXPIRED"));
+    assert!(output.contains(" left"));
+
+    let output = sandbox.run(&["prune", "--expired-certs"]);
+    assert!(output.contains("Removed expired certificate expired"));
+    assert!(!output.contains("certificate valid"));
+    assert!(sandbox.run(&["-s"]).contains("3 identities at"));
+}
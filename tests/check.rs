@@ -0,0 +1,44 @@
+use std::process::Command;
+
+mod run_binary;
+use run_binary::{askpass, Sandbox};
+
+#[test]
+fn check_signing() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent_with_identity();
+
+    let rsa_key = sandbox.dir.join("rsa_key");
+    Command::new("ssh-keygen")
+        .args([
+            "-q", "-t", "rsa", "-b", "2048", "-N", "", "-C", "rsa@test", "-f",
+        ])
+        .arg(&rsa_key)
+        .status()
+        .expect("Unable to generate a key");
+    sandbox.run(&["add", "--agent", &agent.pid, rsa_key.to_str().unwrap()]);
+
+    let output = sandbox.run(&["check", "--agent", &agent.pid]);
+    assert_eq!(output.matches("PASS").count(), 2);
+    assert!(output.contains("rsa@test"));
+
+    // agents without the key aren't checked
+    let _other = sandbox.make_agent_with_identity();
+    let output = sandbox.run(&["check", "--all", "--key", "rsa@test"]);
+    assert_eq!(output.matches("PASS").count(), 1);
+    assert!(!output.contains("FAIL"), "{output}");
+}
+
+#[test]
+fn check_locked_agent_fails() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent_with_identity();
+    let askpass = askpass(&sandbox, "hunter2");
+    sandbox.run_with_env(
+        &["lock", "--agent", &agent.pid],
+        &[("SSH_ASKPASS", askpass.as_str())],
+    );
+
+    let error = sandbox.run_failing(&["check", "--agent", &agent.pid]);
+    assert!(error.contains("1 checks failed"));
+}
@@ -0,0 +1,86 @@
+mod run_binary;
+use run_binary::Sandbox;
+
+#[test]
+fn config_show() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[ez]\npolicy = \"reduce\"\n");
+
+    let output = sandbox.run(&["config", "show", "--shell", "fish"]);
+    assert!(output.contains("policy = \"reduce\""));
+    assert!(output.contains("shell = \"fish\""));
+    assert!(output.contains(sandbox.dir.to_str().unwrap()));
+}
+
+#[test]
+fn shell_format() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[output]\nshell = \"csh\"\n");
+    let agent = sandbox.make_agent();
+
+    let output = sandbox.run(&["-y"]);
+    assert!(output.contains(&format!("setenv SSH_AGENT_PID {};", agent.pid)));
+}
+
+#[test]
+fn protected_agents() {
+    let sandbox = Sandbox::new();
+    let protected = sandbox.make_agent();
+    let _other = sandbox.make_agent();
+    sandbox.write_config(&format!(
+        "[protect]\nsockets = [{:?}]\n",
+        protected.socket_path
+    ));
+
+    sandbox.run(&["-p"]);
+    let output = sandbox.run(&["-s"]);
+    assert_eq!(output.lines().count(), 1);
+    assert!(output.contains(protected.socket_path.to_str().unwrap()));
+}
+
+#[test]
+fn invalid_times_are_rejected() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[daemon]\ninterval = \"5 minutes\"\n");
+
+    let error = sandbox.run_failing(&["-s"]);
+    assert!(error.contains("daemon.interval"), "{error}");
+
+    sandbox.write_config("[idle]\ntimeout = \"a while\"\n");
+    let error = sandbox.run_failing(&["-s"]);
+    assert!(error.contains("idle.timeout"), "{error}");
+
+    // a key must never be loaded without its lifetime
+    for lifetime in ["an hour", "99999999999999999w"] {
+        sandbox.write_config(&format!(
+            "[[keys]]\npath = \"~/.ssh/id_ed25519\"\nlifetime = {:?}\n",
+            lifetime
+        ));
+        let error = sandbox.run_failing(&["-s"]);
+        assert!(error.contains("invalid lifetime"), "{error}");
+    }
+}
+
+#[test]
+fn missing_explicit_config_is_an_error() {
+    let sandbox = Sandbox::new();
+    let missing = sandbox.dir.join("typo.toml");
+
+    let error = sandbox.run_failing(&["--config", missing.to_str().unwrap(), "config", "show"]);
+    assert!(error.contains(missing.to_str().unwrap()), "{error}");
+
+    std::fs::remove_file(&sandbox.config_path).unwrap();
+    let error = sandbox.run_failing(&["config", "show"]);
+    assert!(
+        error.contains(sandbox.config_path.to_str().unwrap()),
+        "{error}"
+    );
+
+    // the default place is optional
+    let config_home = sandbox.dir.join("config");
+    let output = run_binary::run_with_env(
+        &["config", "show"],
+        &[("XDG_CONFIG_HOME", config_home.to_str().unwrap())],
+    );
+    assert!(output.contains("[daemon]"), "{output}");
+}
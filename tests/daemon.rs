@@ -0,0 +1,98 @@
+use std::fs::{self, File};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod run_binary;
+use run_binary::Sandbox;
+
+#[test]
+fn daemon_once_prunes_dead_and_orphaned_agents() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[daemon]\nprune_empty_orphans = true\n");
+    let orphan = sandbox.make_agent();
+    let used = sandbox.make_agent();
+    let loaded = sandbox.make_agent_with_identity();
+    let dead = sandbox.make_agent();
+    Command::new("kill")
+        .args(["-KILL", &dead.pid])
+        .status()
+        .unwrap();
+
+    // a process using the empty agent keeps it alive
+    let mut user = Command::new("sleep")
+        .arg("30")
+        .env("SSH_AUTH_SOCK", &used.socket_path)
+        .spawn()
+        .unwrap();
+    sandbox.run(&["daemon", "--once"]);
+    user.kill().unwrap();
+    user.wait().unwrap();
+
+    assert!(!orphan.socket_path.exists());
+    assert!(!dead.socket_path.exists());
+    assert!(used.socket_path.exists());
+    assert!(loaded.socket_path.exists());
+}
+
+#[test]
+fn daemon_enforces_max_agents_when_agents_appear() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[daemon]\ninterval = \"1h\"\nmax_agents = 1\n");
+    let loaded = sandbox.make_agent_with_identity();
+
+    let log = sandbox.dir.join("daemon.log");
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_ssh-agency"))
+        .arg("daemon")
+        .env("SSH_AGENCY_CONFIG", &sandbox.config_path)
+        .env_remove("SSH_AUTH_SOCK")
+        .stdout(Stdio::null())
+        .stderr(File::create(&log).unwrap())
+        .spawn()
+        .unwrap();
+    // let the first pass finish before the new agent appears
+    thread::sleep(Duration::from_millis(500));
+
+    let extra = sandbox.make_agent();
+    let start = Instant::now();
+    while extra.socket_path.exists() && start.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+
+    assert!(!extra.socket_path.exists());
+    assert!(loaded.socket_path.exists());
+    let log = fs::read_to_string(log).unwrap();
+    assert!(log.contains(&format!(
+        "Agent pid {} at {} is over the `max_agents` limit of 1",
+        extra.pid,
+        extra.socket_path.display()
+    )));
+}
+
+#[test]
+fn daemon_leaves_sockets_of_other_programs_alone() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(
+        "[daemon]\nprune_empty_orphans = true\nmax_agents = 0\n[health]\ntimeout_ms = 200\n",
+    );
+    let socket = sandbox.dir.join("agent.99");
+    let mut listener = Command::new("python3")
+        .arg("-c")
+        .arg("import socket, sys, time; s = socket.socket(socket.AF_UNIX); s.bind(sys.argv[1]); s.listen(); time.sleep(60)")
+        .arg(&socket)
+        .spawn()
+        .unwrap();
+    while !socket.exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    sandbox.run(&["daemon", "--once"]);
+    sandbox.run(&["daemon", "--once"]);
+
+    assert!(socket.exists());
+    assert!(listener.try_wait().unwrap().is_none());
+    listener.kill().unwrap();
+    listener.wait().unwrap();
+}
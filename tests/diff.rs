@@ -0,0 +1,76 @@
+use std::process::{Command, Stdio};
+
+use ssh_agency::agent::identities::key_file_fingerprint;
+mod run_binary;
+use run_binary::{test_identity_path, Sandbox};
+
+#[test]
+fn diff_agents() {
+    let sandbox = Sandbox::new();
+    let shared = sandbox.make_agent_with_identity();
+    let other = sandbox.make_agent_with_identity();
+    let _unrelated = sandbox.make_agent();
+
+    let extra_key = sandbox.dir.join("extra_key");
+    Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "extra@test", "-f"])
+        .arg(&extra_key)
+        .status()
+        .expect("Unable to generate a key");
+    sandbox.run(&["add", "--agent", &other.pid, extra_key.to_str().unwrap()]);
+
+    let output = sandbox.run(&["diff", &shared.pid, &other.pid]);
+    let shared_fingerprint = key_file_fingerprint(&test_identity_path()).unwrap();
+    let (common, unique) = output.split_once("Only in").unwrap();
+    assert!(common.contains(&shared_fingerprint));
+    assert!(!unique.contains(&shared_fingerprint));
+    assert!(unique.contains("extra@test"));
+    assert_eq!(output.matches("Only in").count(), 2);
+
+    let output = sandbox.run(&["diff"]);
+    assert_eq!(output.matches("Only in").count(), 3);
+}
+
+#[test]
+fn diff_counts_a_key_and_its_certificate_once() {
+    let sandbox = Sandbox::new();
+    let certified = sandbox.make_agent();
+    let other = sandbox.make_agent_with_identity();
+
+    let ca = sandbox.dir.join("ca");
+    let key = sandbox.dir.join("certified");
+    for path in [&ca, &key] {
+        Command::new("ssh-keygen")
+            .args([
+                "-q",
+                "-t",
+                "ed25519",
+                "-N",
+                "",
+                "-C",
+                "certified@test",
+                "-f",
+            ])
+            .arg(path)
+            .status()
+            .expect("Unable to generate a key");
+    }
+    Command::new("ssh-keygen")
+        .args(["-q", "-s", ca.to_str().unwrap(), "-I", "certified"])
+        .args(["-n", "alice", "-V", "-1h:+1d"])
+        .arg(key.with_extension("pub"))
+        .status()
+        .expect("Unable to sign the key");
+    // `ssh-add` loads the certificate next to the key too
+    Command::new("ssh-add")
+        .arg(&key)
+        .env("SSH_AUTH_SOCK", &certified.socket_path)
+        .stderr(Stdio::null())
+        .status()
+        .expect("Unable to add the key and its certificate");
+
+    let output = sandbox.run(&["diff", &certified.pid, &other.pid]);
+    let (common, unique) = output.split_once("Only in").unwrap();
+    assert!(common.contains("(none)"), "{output}");
+    assert!(unique.contains("certified@test"), "{output}");
+}
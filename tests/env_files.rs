@@ -0,0 +1,72 @@
+use std::fs;
+
+mod run_binary;
+use run_binary::Sandbox;
+
+fn env_file(sandbox: &Sandbox, shell: &str) -> Option<String> {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+    fs::read_to_string(
+        sandbox
+            .dir
+            .join("env")
+            .join(format!("{}-{}", hostname.trim(), shell)),
+    )
+    .ok()
+}
+
+#[test]
+fn env_files_follow_picked_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(&format!(
+        "[env_files]\nenabled = true\ndir = {:?}\n[ez]\npolicy = \"pick\"\n",
+        sandbox.dir.join("env")
+    ));
+    let first = sandbox.make_agent();
+
+    sandbox.run(&["-y"]);
+    let sh = env_file(&sandbox, "sh").unwrap();
+    assert!(sh.contains(&format!("SSH_AUTH_SOCK={:?}", first.socket_path)));
+    assert!(sh.contains(&format!("SSH_AGENT_PID={}", first.pid)));
+    assert!(env_file(&sandbox, "csh")
+        .unwrap()
+        .contains(&format!("setenv SSH_AGENT_PID {}", first.pid)));
+    assert!(env_file(&sandbox, "fish")
+        .unwrap()
+        .contains(&format!("SSH_AGENT_PID {}", first.pid)));
+
+    let second = sandbox.make_agent_with_identity();
+    sandbox.run(&["-y"]);
+    assert!(env_file(&sandbox, "sh")
+        .unwrap()
+        .contains(&format!("SSH_AGENT_PID={}", second.pid)));
+
+    // killing an agent the files aren't for leaves them alone
+    sandbox.run(&["-p"]);
+    assert!(env_file(&sandbox, "sh").is_some());
+
+    sandbox.run(&["clear", "--agent", &second.pid]);
+    sandbox.run(&["-p"]);
+    assert!(env_file(&sandbox, "sh").is_none());
+    assert!(env_file(&sandbox, "fish").is_none());
+}
+
+#[test]
+fn env_files_are_removed_with_a_dead_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(&format!(
+        "[env_files]\nenabled = true\ndir = {:?}\n",
+        sandbox.dir.join("env")
+    ));
+    let agent = sandbox.make_agent();
+    sandbox.run(&["-y"]);
+    assert!(env_file(&sandbox, "sh").is_some());
+
+    std::process::Command::new("kill")
+        .args(["-KILL", &agent.pid])
+        .status()
+        .unwrap();
+    sandbox.run(&["-s"]);
+    assert!(!agent.socket_path.exists());
+    assert!(env_file(&sandbox, "sh").is_none());
+    assert!(env_file(&sandbox, "csh").is_none());
+}
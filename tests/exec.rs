@@ -0,0 +1,40 @@
+mod run_binary;
+use run_binary::Sandbox;
+
+#[test]
+fn exec_runs_command_with_selected_agent() {
+    let sandbox = Sandbox::new();
+    let first = sandbox.make_agent();
+    let second = sandbox.make_agent_with_identity();
+
+    let script = "echo \"$SSH_AUTH_SOCK $SSH_AGENT_PID\"";
+    let output = sandbox.run(&["exec", &first.pid, "--", "sh", "-c", script]);
+    assert_eq!(
+        output,
+        format!("{} {}", first.socket_path.display(), first.pid)
+    );
+
+    let second_socket = second.socket_path.to_str().unwrap();
+    let output = sandbox.run_with_env(
+        &["exec", "current", "--", "ssh-add", "-l"],
+        &[("SSH_AUTH_SOCK", second_socket)],
+    );
+    assert!(output.contains("ED25519"));
+
+    // the command's failure is the exit status
+    sandbox.run_failing(&["exec", &first.pid, "--", "sh", "-c", "exit 3"]);
+    assert!(sandbox
+        .run_failing(&["exec", "1", "--", "true"])
+        .contains("no running agent matches"));
+}
+
+#[test]
+fn shell_starts_shell_with_selected_agent() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent();
+
+    // with a single agent, no picker is needed
+    let output = sandbox.run_with_env(&["shell"], &[("SHELL", "/usr/bin/env")]);
+    assert!(output.contains(&format!("SSH_AGENT_PID={}", agent.pid)));
+    assert!(output.contains(&format!("SSH_AUTH_SOCK={}", agent.socket_path.display())));
+}
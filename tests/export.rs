@@ -0,0 +1,75 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+mod run_binary;
+use run_binary::{test_identity_path, Sandbox};
+
+fn test_public_key() -> String {
+    let public_key = fs::read_to_string(test_identity_path().with_extension("pub")).unwrap();
+    public_key.trim().to_string()
+}
+
+#[test]
+fn export_authorized_keys() {
+    let sandbox = Sandbox::new();
+    let _agent = sandbox.make_agent_with_identity();
+
+    let output = sandbox.run(&["export"]);
+    assert_eq!(output, test_public_key());
+
+    let output = sandbox.run(&["export", "--restrict", "--from", "10.0.0.0/8"]);
+    assert_eq!(
+        output,
+        format!("restrict,from=\"10.0.0.0/8\" {}", test_public_key())
+    );
+
+    // patterns can't add options or lines of their own
+    for from in ["*\",command=\"sh", "10.0.0.1\nssh-ed25519 AAAA"] {
+        let output = sandbox.run_failing(&["export", "--from", from]);
+        assert!(output.contains("host or address patterns"), "{output}");
+    }
+
+    let output = sandbox.run_failing(&["export", "--key", "no-such-comment"]);
+    assert!(output.contains("no matching identities"));
+}
+
+#[test]
+fn export_replaces_control_characters_in_comments() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent();
+    let key = sandbox.dir.join("sneaky");
+    Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key)
+        .args(["-C", "me\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIM attacker"])
+        .status()
+        .unwrap();
+    let status = Command::new("ssh-add")
+        .arg(&key)
+        .env("SSH_AUTH_SOCK", &agent.socket_path)
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = sandbox.run(&["export", "--restrict"]);
+    assert_eq!(output.lines().count(), 1, "{output}");
+    assert!(output.ends_with(" me?ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIM attacker"));
+}
+
+#[test]
+fn export_pub_files() {
+    let sandbox = Sandbox::new();
+    let _agent = sandbox.make_agent_with_identity();
+    let dir = sandbox.dir.join("exported");
+
+    sandbox.run(&["export", "--dir", dir.to_str().unwrap()]);
+    let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+    assert_eq!(files.len(), 1);
+    let path = files[0].as_ref().unwrap().path();
+    assert!(path.extension().is_some_and(|e| e == "pub"));
+    assert_eq!(fs::read_to_string(path).unwrap().trim(), test_public_key());
+
+    // existing files aren't overwritten
+    sandbox.run_failing(&["export", "--dir", dir.to_str().unwrap()]);
+}
@@ -0,0 +1,59 @@
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod run_binary;
+use run_binary::Sandbox;
+
+fn signal(pid: &str, signal: &str) {
+    Command::new("kill")
+        .args([signal, pid])
+        .status()
+        .expect("Unable to signal agent");
+}
+
+#[test]
+fn hung_agents() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[health]\ntimeout_ms = 300\n");
+    let hung = sandbox.make_agent();
+    let _healthy = sandbox.make_agent_with_identity();
+    // a stopped agent still accepts connections, but never answers
+    signal(&hung.pid, "-STOP");
+
+    let start = Instant::now();
+    let output = sandbox.run(&["-s"]);
+    assert!(start.elapsed() < Duration::from_secs(30));
+    assert!(output.contains(&format!("PID {}: Hung", hung.pid)));
+    assert!(output.contains("1 identity"));
+
+    sandbox.run(&["-p"]);
+    // the agent only acts on the termination signal once it runs again
+    signal(&hung.pid, "-CONT");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while hung.socket_path.exists() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!hung.socket_path.exists());
+    assert!(!sandbox.run(&["-s"]).contains("Hung"));
+}
+
+#[test]
+fn hung_agents_are_probed_in_parallel() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[health]\ntimeout_ms = 1500\nworkers = 4\n");
+    let hung: Vec<_> = (0..4).map(|_| sandbox.make_agent()).collect();
+    for agent in &hung {
+        signal(&agent.pid, "-STOP");
+    }
+
+    // probed one at a time, listing these would take at least 6 seconds
+    let start = Instant::now();
+    let output = sandbox.run(&["-s"]);
+    let took = start.elapsed();
+    for agent in &hung {
+        signal(&agent.pid, "-CONT");
+    }
+    assert_eq!(output.matches("Hung").count(), 4);
+    assert!(took < Duration::from_millis(4500), "took {:?}", took);
+}
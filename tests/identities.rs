@@ -0,0 +1,52 @@
+use ssh_agency::agent::identities::key_file_fingerprint;
+mod run_binary;
+use run_binary::{test_identity_path, Sandbox};
+
+#[test]
+fn add_and_remove_by_file() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent();
+    let key = test_identity_path();
+
+    let output = sandbox.run(&[
+        "add",
+        "--agent",
+        &agent.pid,
+        "-t",
+        "1h",
+        key.to_str().unwrap(),
+    ]);
+    assert!(output.contains("Identity added"));
+    assert!(sandbox.run(&["-s"]).contains("1 identity"));
+
+    let public_key = key.with_extension("pub");
+    sandbox.run(&[
+        "remove",
+        "--agent",
+        agent.socket_path.to_str().unwrap(),
+        public_key.to_str().unwrap(),
+    ]);
+    assert!(sandbox.run(&["-s"]).contains("No identities"));
+}
+
+#[test]
+fn remove_by_fingerprint() {
+    let sandbox = Sandbox::new();
+    let _agent = sandbox.make_agent_with_identity();
+    let fingerprint = key_file_fingerprint(&test_identity_path()).unwrap();
+
+    let output = sandbox.run(&["remove", &fingerprint]);
+    assert!(output.contains(&fingerprint));
+    assert!(sandbox.run(&["-s"]).contains("No identities"));
+}
+
+#[test]
+fn clear_identities() {
+    let sandbox = Sandbox::new();
+    let _empty = sandbox.make_agent();
+    let agent = sandbox.make_agent_with_identity();
+
+    sandbox.run(&["clear", "--agent", &agent.pid]);
+    let output = sandbox.run(&["-s"]);
+    assert!(!output.contains("1 identity"));
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+mod run_binary;
+use run_binary::{askpass, Sandbox};
+
+/// Make the socket at `path` look unused for `ago`, like `10 hours ago`.
+///
+/// This changes the socket's status change time to now, so the access time isn't newer than it
+/// and no `relatime` lag is taken off, like after Agency puts the access time back.
+fn set_last_used(path: &Path, ago: &str) {
+    let status = Command::new("touch")
+        .args(["-a", "-h", "-d", ago])
+        .arg(path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn prune_idle_clears_and_kills_idle_agents() {
+    let sandbox = Sandbox::new();
+    let idle = sandbox.make_agent_with_identity();
+    let busy = sandbox.make_agent_with_identity();
+    set_last_used(&idle.socket_path, "10 hours ago");
+    set_last_used(&busy.socket_path, "1 hour ago");
+
+    // Agency's own connections don't count as use
+    sandbox.run(&["-s"]);
+    sandbox.run(&["prune", "--idle", "8h"]);
+    let output = sandbox.run(&["-s"]);
+    assert!(output.contains(&format!("PID {}: No identities", idle.pid)));
+    assert!(output.contains(&format!("PID {}: 1 identity", busy.pid)));
+
+    set_last_used(&busy.socket_path, "3 days ago");
+    sandbox.run(&["prune", "--idle", "2d", "--idle-action", "kill"]);
+    assert!(!busy.socket_path.exists());
+    assert!(idle.socket_path.exists());
+}
+
+#[test]
+fn prune_idle_locks_idle_agents() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[idle]\naction = \"lock\"\n");
+    let agent = sandbox.make_agent_with_identity();
+    set_last_used(&agent.socket_path, "2 days ago");
+
+    let askpass = askpass(&sandbox, "hunter2");
+    sandbox.run_with_env(
+        &["prune", "--idle", "1d"],
+        &[("SSH_ASKPASS", askpass.as_str())],
+    );
+    assert!(sandbox.run(&["-s"]).contains("Locked"));
+}
+
+#[test]
+fn daemon_locks_idle_agents_with_the_passphrase_file() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[idle]\ntimeout = \"1d\"\naction = \"lock\"\n");
+    let error = sandbox.run_failing(&["daemon", "--once"]);
+    assert!(error.contains("idle.passphrase_file"), "{error}");
+
+    let passphrase_file = sandbox.dir.join("passphrase");
+    fs::write(&passphrase_file, "hunter2\n").unwrap();
+    sandbox.write_config(&format!(
+        "[idle]\ntimeout = \"1d\"\naction = \"lock\"\npassphrase_file = {:?}\n",
+        passphrase_file
+    ));
+    let agent = sandbox.make_agent_with_identity();
+    set_last_used(&agent.socket_path, "3 days ago");
+
+    sandbox.run(&["daemon", "--once"]);
+    assert!(sandbox.run(&["-s"]).contains("Locked"));
+}
@@ -0,0 +1,79 @@
+use std::process::{Command, Stdio};
+
+mod run_binary;
+use run_binary::{test_identity_path, Sandbox};
+
+fn key_dir_config() -> String {
+    let key_dir = test_identity_path().parent().unwrap().to_path_buf();
+    format!("[inventory]\nkey_dirs = [{:?}]\n", key_dir)
+}
+
+#[test]
+fn inventory_table() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(&key_dir_config());
+    let _empty = sandbox.make_agent();
+
+    let output = sandbox.run(&["inventory"]);
+    assert!(output.contains("nowhere"));
+    assert!(output.contains("id_ed25519_key.pub"));
+
+    let first = sandbox.make_agent_with_identity();
+    let second = sandbox.make_agent_with_identity();
+    let output = sandbox.run(&["inventory"]);
+    assert!(output.contains("duplicated"));
+    assert!(output.contains(&first.pid));
+    assert!(output.contains(&second.pid));
+}
+
+#[test]
+fn inventory_json() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(&key_dir_config());
+    let agent = sandbox.make_agent_with_identity();
+
+    let output = sandbox.run(&["inventory", "--json"]);
+    let inventory: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let entry = &inventory[0];
+    assert_eq!(entry["placement"], "once");
+    assert_eq!(entry["agents"][0]["pid"], agent.pid.as_str());
+}
+
+#[test]
+fn inventory_counts_a_key_and_its_certificate_once() {
+    let sandbox = Sandbox::new();
+    let key_dir = sandbox.dir.join("keys");
+    std::fs::create_dir(&key_dir).unwrap();
+    sandbox.write_config(&format!("[inventory]\nkey_dirs = [{:?}]\n", key_dir));
+    let agent = sandbox.make_agent();
+
+    let ca = sandbox.dir.join("ca");
+    let key = key_dir.join("certified");
+    for args in [
+        vec!["-t", "ed25519", "-N", "", "-f", ca.to_str().unwrap()],
+        vec!["-t", "ed25519", "-N", "", "-f", key.to_str().unwrap()],
+    ] {
+        Command::new("ssh-keygen")
+            .arg("-q")
+            .args(args)
+            .status()
+            .unwrap();
+    }
+    Command::new("ssh-keygen")
+        .args(["-q", "-s", ca.to_str().unwrap(), "-I", "certified"])
+        .args(["-n", "alice", "-V", "-1h:+1d"])
+        .arg(key.with_extension("pub"))
+        .status()
+        .unwrap();
+    Command::new("ssh-add")
+        .arg(&key)
+        .env("SSH_AUTH_SOCK", &agent.socket_path)
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+
+    let output = sandbox.run(&["inventory", "--json"]);
+    let inventory: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(inventory[0]["placement"], "once", "{output}");
+    assert_eq!(inventory[0]["agents"].as_array().unwrap().len(), 1);
+}
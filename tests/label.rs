@@ -0,0 +1,52 @@
+use ssh_agency::agent::spawner::AgentSpawner;
+
+mod run_binary;
+use run_binary::Sandbox;
+
+#[test]
+fn labels_show_in_listings_and_select_agents() {
+    let sandbox = Sandbox::new();
+    let work = sandbox.make_agent_with_identity();
+    let other = sandbox.make_agent();
+
+    sandbox.run(&["label", &work.pid, "work"]);
+    assert!(sandbox
+        .run(&["-s"])
+        .contains(&format!("PID {} [work]: 1 identity", work.pid)));
+
+    let output = sandbox.run(&["env", "--agent", "work"]);
+    assert!(output.contains(&format!("SSH_AGENT_PID={}", work.pid)));
+    let output = sandbox.run(&["exec", "work", "--", "sh", "-c", "echo $SSH_AGENT_PID"]);
+    assert_eq!(output, work.pid);
+
+    // a label belongs to one agent at a time
+    sandbox.run(&["label", &other.pid, "work"]);
+    let output = sandbox.run(&["-s"]);
+    assert!(output.contains(&format!("PID {} [work]:", other.pid)));
+    assert!(output.contains(&format!("PID {}:", work.pid)));
+
+    sandbox.run(&["label", "work", "--remove"]);
+    assert!(!sandbox.run(&["-s"]).contains("[work]"));
+    assert!(sandbox
+        .run_failing(&["env", "--agent", "work"])
+        .contains("no running agent matches `work`"));
+    assert!(sandbox
+        .run_failing(&["label", &work.pid, "123"])
+        .contains("would be read as"));
+}
+
+#[test]
+fn labels_do_not_carry_over_to_new_agents_on_the_same_socket() {
+    let sandbox = Sandbox::new();
+    let mut first = sandbox.make_agent();
+    sandbox.run(&["label", &first.pid, "old"]);
+    first.kill_and_clean_agent();
+
+    let mut second = AgentSpawner::new()
+        .socket_path(&first.socket_path)
+        .spawn()
+        .unwrap();
+    let output = sandbox.run(&["-s"]);
+    second.kill_and_clean_agent();
+    assert!(!output.contains("[old]"));
+}
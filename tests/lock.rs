@@ -0,0 +1,34 @@
+mod run_binary;
+use run_binary::{askpass, Sandbox};
+
+#[test]
+fn lock_and_unlock() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent_with_identity();
+    let askpass = askpass(&sandbox, "hunter2");
+    let env = [("SSH_ASKPASS", askpass.as_str())];
+
+    let output = sandbox.run_with_env(&["lock", "--agent", &agent.pid], &env);
+    assert!(output.contains("locked"));
+    assert!(sandbox.run(&["-s"]).contains("Locked"));
+
+    sandbox.run_with_env(&["unlock", "--all"], &env);
+    assert!(sandbox.run(&["-s"]).contains("1 identity"));
+}
+
+#[test]
+fn lock_all() {
+    let sandbox = Sandbox::new();
+    let _empty = sandbox.make_agent();
+    let _agent = sandbox.make_agent_with_identity();
+    let askpass = askpass(&sandbox, "hunter2");
+    let env = [("SSH_ASKPASS", askpass.as_str())];
+
+    sandbox.run_with_env(&["lock", "--all"], &env);
+    let output = sandbox.run(&["-s"]);
+    assert_eq!(output.matches("Locked").count(), 2);
+
+    // already locked agents are skipped
+    let output = sandbox.run_with_env(&["lock", "--all"], &env);
+    assert!(output.contains("No agents to lock"));
+}
@@ -1,57 +1,55 @@
-use std::process::Command;
-
-use ssh_agency::agent::{
-    running_agents::{get_current_agents, resolve_agent_pids},
-    Agent,
-};
+use ssh_agency::agent::Agent;
 mod run_binary;
+use run_binary::Sandbox;
 
 #[test]
 fn purge_agents() {
+    let sandbox = Sandbox::new();
+
     // try with 1 empty agent
-    let _fresh_agent = Command::new("ssh-agent")
-        .arg("-s")
-        .status()
-        .expect("Unable to start SSH agent");
-    assert_ne!(run_binary::run(&["-p"]), String::new());
+    let _fresh_agent = sandbox.make_agent();
+    sandbox.run(&["-p"]);
+    assert_eq!(sandbox.run(&["-s"]), "No running agents");
 
     // try with 0
-    assert_eq!(run_binary::run(&["-p"]), String::new());
+    assert_eq!(sandbox.run(&["-p"]), String::new());
 
     // try with 1 agent with an identity loaded
-    let mut agent = make_agent_with_identity();
+    let mut agent = sandbox.make_agent_with_identity();
 
     agent.kill_and_clean_agent();
-    assert_eq!(run_binary::run(&["-p"]), String::new());
+    assert_eq!(sandbox.run(&["-p"]), String::new());
 }
 
 #[test]
 fn show_agents() {
-    assert_eq!(run_binary::run(&["-s"]), "No running agents");
+    let sandbox = Sandbox::new();
+    assert_eq!(sandbox.run(&["-s"]), "No running agents");
 
-    let mut agent = make_agent();
-    let output = run_binary::run(&["-s"]);
+    let mut agent = sandbox.make_agent();
+    let output = sandbox.run(&["-s"]);
     agent.kill_and_clean_agent();
     assert!(output.contains("No identities"));
     assert!(output.contains(&agent.pid.to_string()));
-    assert!(output.contains(&agent.socket_path.to_str().unwrap()));
+    assert!(output.contains(agent.socket_path.to_str().unwrap()));
 
-    let mut agent_with_ids = make_agent_with_identity();
-    let output = run_binary::run(&["-s"]);
+    let mut agent_with_ids = sandbox.make_agent_with_identity();
+    let output = sandbox.run(&["-s"]);
     agent_with_ids.kill_and_clean_agent();
     assert!(output.contains("1 identity"));
     assert!(output.contains(&agent_with_ids.pid.to_string()));
-    assert!(output.contains(&agent_with_ids.socket_path.to_str().unwrap()));
+    assert!(output.contains(agent_with_ids.socket_path.to_str().unwrap()));
 }
 
 #[test]
 fn reduce_by_count() {
-    let agents: Vec<Agent> = (1..=5).map(|s| make_agent()).collect();
-    let mut agent_with_identity = make_agent_with_identity();
-    assert_eq!(run_binary::run(&["-s"]).lines().count(), 6);
+    let sandbox = Sandbox::new();
+    let agents: Vec<Agent> = (1..=5).map(|_| sandbox.make_agent()).collect();
+    let mut agent_with_identity = sandbox.make_agent_with_identity();
+    assert_eq!(sandbox.run(&["-s"]).lines().count(), 6);
 
-    run_binary::run(&["-n"]);
-    let output = run_binary::run(&["-s"]);
+    sandbox.run(&["-n"]);
+    let output = sandbox.run(&["-s"]);
     assert_eq!(output.lines().count(), 1);
     assert!(output.contains(&agent_with_identity.pid));
     agent_with_identity.kill_and_clean_agent();
@@ -66,40 +64,129 @@ fn reduce_by_count() {
 
 #[test]
 fn reduce_simple() {
-    let agents: Vec<Agent> = (1..=5).map(|s| make_agent()).collect();
-    assert_eq!(run_binary::run(&["-s"]).lines().count(), 5);
+    let sandbox = Sandbox::new();
+    let agents: Vec<Agent> = (1..=5).map(|_| sandbox.make_agent()).collect();
+    assert_eq!(sandbox.run(&["-s"]).lines().count(), 5);
 
-    run_binary::run(&["-r"]);
-    assert_eq!(run_binary::run(&["-s"]).lines().count(), 1);
+    sandbox.run(&["-r"]);
+    assert_eq!(sandbox.run(&["-s"]).lines().count(), 1);
     for mut a in agents {
         a.kill_and_clean_agent();
     }
 }
 
-fn make_agent() -> Agent {
-    let _fresh_agent = Command::new("ssh-agent")
-        .arg("-s")
-        .status()
-        .expect("Unable to start SSH agent");
-    let agents = get_current_agents().expect("Unable to get test agent");
-    let agents = resolve_agent_pids(&agents);
-    let agent = agents.last().unwrap().clone();
-    agent
+#[test]
+fn ez_multiple_agents() {
+    let sandbox = Sandbox::new();
+    let agents: Vec<Agent> = (1..=3).map(|_| sandbox.make_agent()).collect();
+    assert_eq!(sandbox.run(&["-y"]), "echo Too many running agents");
+
+    let output = sandbox.run(&["-y", "--ez-policy", "pick", "--ez-strategy", "simple"]);
+    assert!(output.contains("export SSH_AUTH_SOCK="));
+    assert_eq!(sandbox.run(&["-s"]).lines().count(), 3);
+
+    let output = sandbox.run(&["-y", "--ez-policy", "reduce"]);
+    assert!(output.contains("export SSH_AUTH_SOCK="));
+    assert_eq!(sandbox.run(&["-s"]).lines().count(), 1);
+    for mut a in agents {
+        a.kill_and_clean_agent();
+    }
+}
+
+#[test]
+fn current_agent() {
+    let sandbox = Sandbox::new();
+    let mut agent = sandbox.make_agent();
+    let sock = agent.socket_path.to_str().unwrap().to_string();
+
+    let output = sandbox.run_with_env(&["-s"], &[("SSH_AUTH_SOCK", &sock)]);
+    assert!(output.contains(", current)"));
+
+    let output = sandbox.run_with_env(
+        &["-y", "--ez-policy", "current"],
+        &[("SSH_AUTH_SOCK", &sock), ("SSH_AGENT_PID", &agent.pid)],
+    );
+    assert!(output.contains(&sock));
+
+    agent.kill_and_clean_agent();
+}
+
+#[test]
+fn foreign_agents_are_never_killed() {
+    let sandbox = Sandbox::new();
+    // a forwarded agent, like sshd's, that answers with no identities
+    let socket_dir = sandbox.dir.join("ssh-fwd");
+    std::fs::create_dir(&socket_dir).unwrap();
+    let socket = socket_dir.join("agent.4242");
+    let mut listener = std::process::Command::new("python3")
+        .arg("-c")
+        .arg(
+            "import socket, sys
+s = socket.socket(socket.AF_UNIX); s.bind(sys.argv[1]); s.listen()
+while True:
+    c, _ = s.accept()
+    while (n := c.recv(4)):
+        body = c.recv(int.from_bytes(n, 'big'))
+        c.sendall(b'\\0\\0\\0\\x05\\x0c\\0\\0\\0\\0' if body[:1] == b'\\x0b' else b'\\0\\0\\0\\x01\\x05')
+    c.close()",
+        )
+        .arg(&socket)
+        .spawn()
+        .unwrap();
+    while !socket.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let _agent = sandbox.make_agent_with_identity();
+
+    let output = sandbox.run(&["-s"]);
+    assert!(
+        output.contains(&format!(
+            "PID {}: No identities at {} (Running, foreign)",
+            listener.id(),
+            socket.display()
+        )),
+        "{output}"
+    );
+    sandbox.run(&["-p"]);
+    sandbox.run(&["-r"]);
+
+    assert!(socket.exists());
+    assert!(listener.try_wait().unwrap().is_none());
+    listener.kill().unwrap();
+    listener.wait().unwrap();
 }
 
-fn make_agent_with_identity() -> Agent {
-    let _fresh_agent = Command::new("ssh-agent")
-        .arg("-s")
-        .status()
-        .expect("Unable to start SSH agent");
-    let agents = get_current_agents().expect("Unable to get test agent");
-    let agents = resolve_agent_pids(&agents);
-    let agent = agents.last().unwrap().clone();
-    Command::new("ssh-add")
-        .arg("./tests/data/id_ed25519_key")
-        .env("SSH_AUTH_SOCK", &agent.socket_path)
-        .env("SSH_AGENT_PID", &agent.pid)
-        .status()
-        .expect("Unable to add test identity");
-    agent
+#[test]
+fn non_agent_sockets_are_left_alone() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[health]\ntimeout_ms = 200");
+    let sockets = [sandbox.dir.join("other.sock"), sandbox.dir.join("agent.99")];
+    let mut listeners: Vec<_> = sockets
+        .iter()
+        .map(|socket| {
+            std::process::Command::new("python3")
+                .arg("-c")
+                .arg("import socket, sys, time; s = socket.socket(socket.AF_UNIX); s.bind(sys.argv[1]); s.listen(); time.sleep(60)")
+                .arg(socket)
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    while !sockets.iter().all(|s| s.exists()) {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let _agent = sandbox.make_agent();
+    let _other_agent = sandbox.make_agent();
+
+    let output = sandbox.run(&["-s"]);
+    assert_eq!(output.lines().count(), 2, "{output}");
+    sandbox.run(&["-n"]);
+    sandbox.run(&["-s"]);
+
+    for (socket, listener) in sockets.iter().zip(&mut listeners) {
+        assert!(socket.exists());
+        assert!(listener.try_wait().unwrap().is_none());
+        listener.kill().unwrap();
+        listener.wait().unwrap();
+    }
 }
@@ -0,0 +1,124 @@
+use std::process::{Command, Stdio};
+
+mod run_binary;
+use run_binary::{askpass, test_identity_path, Sandbox};
+
+#[test]
+fn pinned_agents_survive_purges_and_reductions() {
+    let sandbox = Sandbox::new();
+    let pinned = sandbox.make_agent();
+    let loaded = sandbox.make_agent_with_identity();
+    let empty = sandbox.make_agent();
+
+    sandbox.run(&["pin", &pinned.pid]);
+    assert!(sandbox.run(&["-s"]).contains(&format!(
+        "at {} (Running, pinned)",
+        pinned.socket_path.display()
+    )));
+
+    let (_, stderr) = sandbox.run_with_stderr(&["-p"], &[]);
+    assert!(stderr.contains(&format!(
+        "Leaving agent pid {} alone: it is pinned",
+        pinned.pid
+    )));
+    assert!(pinned.socket_path.exists());
+    assert!(!empty.socket_path.exists());
+
+    sandbox.run(&["-n"]);
+    assert!(pinned.socket_path.exists());
+    assert!(loaded.socket_path.exists());
+
+    sandbox.run(&["unpin", &pinned.pid]);
+    sandbox.run(&["-n"]);
+    assert!(!pinned.socket_path.exists());
+}
+
+#[test]
+fn agents_holding_protected_keys_survive_reductions() {
+    let sandbox = Sandbox::new();
+    let loaded = sandbox.make_agent_with_identity();
+    let output = Command::new("ssh-keygen")
+        .arg("-lf")
+        .arg(test_identity_path())
+        .output()
+        .unwrap();
+    let fingerprint = String::from_utf8(output.stdout).unwrap();
+    let fingerprint = fingerprint.split_whitespace().nth(1).unwrap();
+    sandbox.write_config(&format!(
+        "[protect]\nfingerprints = [{:?}]\n[daemon]\nmax_agents = 1\n",
+        fingerprint
+    ));
+
+    // an agent with more identities ranks above the protected one
+    let other = sandbox.make_agent();
+    for name in ["first", "second"] {
+        let key = sandbox.dir.join(name);
+        Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        Command::new("ssh-add")
+            .arg(&key)
+            .env("SSH_AUTH_SOCK", &other.socket_path)
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+    }
+
+    let (_, stderr) = sandbox.run_with_stderr(&["daemon", "--once"], &[]);
+    assert!(stderr.contains(&format!(
+        "Leaving agent pid {} alone: it holds {} from `protect.fingerprints`",
+        loaded.pid, fingerprint
+    )));
+    assert!(loaded.socket_path.exists());
+    assert!(other.socket_path.exists());
+}
+
+#[test]
+fn locked_agents_survive_while_keys_are_protected() {
+    let sandbox = Sandbox::new();
+    let locked = sandbox.make_agent_with_identity();
+    let output = Command::new("ssh-keygen")
+        .arg("-lf")
+        .arg(test_identity_path())
+        .output()
+        .unwrap();
+    let fingerprint = String::from_utf8(output.stdout).unwrap();
+    let fingerprint = fingerprint.split_whitespace().nth(1).unwrap();
+    sandbox.write_config(&format!(
+        "[protect]\nfingerprints = [{:?}]\n[daemon]\nmax_agents = 1\n",
+        fingerprint
+    ));
+    let askpass = askpass(&sandbox, "hunter2");
+    sandbox.run_with_env(
+        &["lock", "--agent", &locked.pid],
+        &[("SSH_ASKPASS", askpass.as_str())],
+    );
+    let other = sandbox.make_agent_with_identity();
+
+    let (_, stderr) = sandbox.run_with_stderr(&["daemon", "--once"], &[]);
+    assert!(stderr.contains(&format!(
+        "Leaving agent pid {} alone: its keys can't be checked against `protect.fingerprints`, it is locked",
+        locked.pid
+    )), "{stderr}");
+    assert!(other.socket_path.exists());
+    sandbox.run(&["-r"]);
+    assert!(locked.socket_path.exists());
+}
+
+#[test]
+fn nothing_is_killed_when_pins_cant_be_read() {
+    let sandbox = Sandbox::new();
+    let empty = sandbox.make_agent();
+    let state_dir = sandbox.state_home.join("ssh-agency");
+    std::fs::create_dir_all(&state_dir).unwrap();
+    std::fs::write(state_dir.join("agents.json"), "{ not json").unwrap();
+
+    let (_, stderr) = sandbox.run_with_stderr(&["-p"], &[]);
+    assert!(stderr.contains(&format!(
+        "Leaving agent pid {} alone: its pin can't be checked",
+        empty.pid
+    )));
+    assert!(empty.socket_path.exists());
+}
@@ -1,27 +1,180 @@
-use std::process::Command;
+// shared by several test crates, which each use only some of the helpers
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
 use std::process::Stdio;
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ssh_agency::agent::{spawner::AgentSpawner, Agent};
+
+/// Run the binary with only the given agent variables set, rather than the test runner's.
+pub fn run_with_env(args: &[&str], envs: &[(&str, &str)]) -> String {
+    let output = output_with_env(args, envs);
+    if !output.status.success() {
+        let err_msg = String::from_utf8(output.stderr).unwrap();
+        panic!("{err_msg}");
+    }
+
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// Run the binary like `run_with_env`, expecting it to fail, and return what it printed to stderr.
+pub fn run_failing_with_env(args: &[&str], envs: &[(&str, &str)]) -> String {
+    let output = output_with_env(args, envs);
+    assert!(
+        !output.status.success(),
+        "{:?} unexpectedly succeeded",
+        args
+    );
+    String::from_utf8(output.stderr).unwrap().trim().to_string()
+}
 
-pub fn run(args: &[&str]) -> String {
+pub fn output_with_env(args: &[&str], envs: &[(&str, &str)]) -> Output {
     let mut cmd = Command::new("cargo");
-    cmd.args(["run", "--"]);
+    cmd.args(["run", "-q", "--"]);
+    cmd.env_remove("SSH_AUTH_SOCK").env_remove("SSH_AGENT_PID");
+    cmd.envs(envs.iter().copied());
 
     for arg in args {
         cmd.arg(arg);
     }
 
-    let output = cmd
-        .stdin(Stdio::null())
+    cmd.stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
         .unwrap()
         .wait_with_output()
-        .unwrap();
+        .unwrap()
+}
 
-    if !output.status.success() {
-        let err_msg = String::from_utf8(output.stderr).unwrap();
-        panic!("{err_msg}");
+/// An isolated directory of agents, with a configuration that only discovers agents inside it.
+///
+/// Agents started through the sandbox are killed and the directory removed when it's dropped, so
+/// tests can run concurrently without seeing each other's agents.
+pub struct Sandbox {
+    pub dir: PathBuf,
+    pub config_path: PathBuf,
+    pub state_home: PathBuf,
+    agents: RefCell<Vec<Agent>>,
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "agency-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Unable to create sandbox");
+
+        let sandbox = Sandbox {
+            config_path: dir.join("config.toml"),
+            state_home: dir.join("state"),
+            dir,
+            agents: RefCell::new(vec![]),
+        };
+        sandbox.write_config("");
+        sandbox
     }
 
-    String::from_utf8(output.stdout).unwrap().trim().to_string()
+    /// Replace the sandbox configuration with `extra` on top of the sandbox discovery path.
+    pub fn write_config(&self, extra: &str) {
+        let config = format!("{}\n[discovery]\npaths = [{:?}]\n", extra.trim(), self.dir);
+        fs::write(&self.config_path, config).expect("Unable to write sandbox config");
+    }
+
+    pub fn run(&self, args: &[&str]) -> String {
+        self.run_with_env(args, &[])
+    }
+
+    pub fn run_with_env(&self, args: &[&str], envs: &[(&str, &str)]) -> String {
+        run_with_env(args, &self.envs(envs))
+    }
+
+    /// Run the binary like `run_with_env`, returning what it printed to stdout and to stderr.
+    pub fn run_with_stderr(&self, args: &[&str], envs: &[(&str, &str)]) -> (String, String) {
+        let output = output_with_env(args, &self.envs(envs));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(output.status.success(), "{stderr}");
+        (String::from_utf8(output.stdout).unwrap(), stderr)
+    }
+
+    pub fn run_failing(&self, args: &[&str]) -> String {
+        run_failing_with_env(args, &self.envs(&[]))
+    }
+
+    /// `envs` with the sandbox configuration and a state directory inside the sandbox added.
+    fn envs<'a>(&'a self, envs: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+        let mut envs = envs.to_vec();
+        envs.push(("SSH_AGENCY_CONFIG", self.config_path.to_str().unwrap()));
+        envs.push(("XDG_STATE_HOME", self.state_home.to_str().unwrap()));
+        envs
+    }
+
+    pub fn make_agent(&self) -> Agent {
+        let mut agents = self.agents.borrow_mut();
+        let agent = AgentSpawner::new()
+            .socket_path(self.dir.join(format!("agent.{}", agents.len())))
+            .spawn()
+            .expect("Unable to start SSH agent");
+        agents.push(agent.clone());
+        agent
+    }
+
+    pub fn make_agent_with_identity(&self) -> Agent {
+        let agent = self.make_agent();
+        add_test_identity(&agent);
+        agent
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        for mut a in self.agents.take() {
+            if a.socket_path.exists() {
+                a.kill_and_clean_agent();
+            }
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Write an `$SSH_ASKPASS` program into the sandbox that answers every prompt with `passphrase`.
+pub fn askpass(sandbox: &Sandbox, passphrase: &str) -> String {
+    let path = sandbox.dir.join("askpass");
+    fs::write(&path, format!("#!/bin/sh\necho {}\n", passphrase)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o700)).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// The test identity's private key, with permissions `ssh-add` accepts.
+pub fn test_identity_path() -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/id_ed25519_key");
+    // ssh-add refuses keys readable by others, which a fresh checkout may leave them as
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+        .expect("Unable to restrict test identity permissions");
+    path
+}
+
+pub fn add_test_identity(agent: &Agent) {
+    Command::new("ssh-add")
+        .arg(test_identity_path())
+        .env("SSH_AUTH_SOCK", &agent.socket_path)
+        .env("SSH_AGENT_PID", &agent.pid)
+        .stderr(Stdio::null())
+        .status()
+        .expect("Unable to add test identity");
 }
@@ -0,0 +1,50 @@
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ssh_agency::agent::identities::AgentIdentityStatus;
+use ssh_agency::agent::spawner::{AgentSpawner, SpawnMode};
+mod run_binary;
+use run_binary::Sandbox;
+
+#[test]
+fn spawn_with_socket_path() {
+    let sandbox = Sandbox::new();
+    let socket_path = sandbox.dir.join("agent.sock");
+
+    let mut agent = AgentSpawner::new()
+        .socket_path(&socket_path)
+        .lifetime("1h")
+        .spawn()
+        .expect("Unable to start SSH agent");
+    assert_eq!(agent.socket_path, socket_path);
+    assert!(agent.is_running);
+    assert!(matches!(
+        agent.check_agent_identities().unwrap(),
+        AgentIdentityStatus::NoIdentities
+    ));
+
+    agent.kill_and_clean_agent();
+}
+
+#[test]
+fn spawn_foreground() {
+    let sandbox = Sandbox::new();
+    let mut agent = AgentSpawner::new()
+        .socket_path(sandbox.dir.join("agent.sock"))
+        .mode(SpawnMode::Foreground)
+        .spawn()
+        .expect("Unable to start SSH agent");
+    assert!(agent.is_running);
+
+    agent.kill_and_clean_agent();
+    assert!(!agent.is_running);
+
+    // the killed agent is reaped rather than left as a zombie
+    let proc_dir = Path::new("/proc").join(&agent.pid);
+    let start = Instant::now();
+    while proc_dir.exists() && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!proc_dir.exists());
+}
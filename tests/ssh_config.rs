@@ -0,0 +1,74 @@
+use std::fs;
+use std::process::Command;
+
+mod run_binary;
+use run_binary::Sandbox;
+
+/// The `identityagent` ssh would use for `host` with a config including the generated file.
+fn identity_agent(sandbox: &Sandbox, host: &str) -> String {
+    let config = sandbox.dir.join("ssh_config");
+    fs::write(
+        &config,
+        format!("Include {}\n", sandbox.dir.join("agency.conf").display()),
+    )
+    .unwrap();
+    let output = Command::new("ssh")
+        .args(["-G", "-F", config.to_str().unwrap(), host])
+        .env_remove("SSH_AUTH_SOCK")
+        .output()
+        .expect("Unable to run ssh");
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .find_map(|l| l.strip_prefix("identityagent "))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[test]
+fn ssh_config_include_follows_picked_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(&format!(
+        "[ssh_config]\nenabled = true\npath = {:?}\nhosts = [\"*.example.com\"]\n[ez]\npolicy = \"pick\"\n",
+        sandbox.dir.join("agency.conf")
+    ));
+    let first = sandbox.make_agent();
+
+    sandbox.run(&["-y"]);
+    let first_socket = first.socket_path.to_str().unwrap();
+    assert_eq!(identity_agent(&sandbox, "git.example.com"), first_socket);
+    assert_ne!(identity_agent(&sandbox, "example.org"), first_socket);
+
+    let second = sandbox.make_agent_with_identity();
+    sandbox.run(&["-y"]);
+    assert_eq!(
+        identity_agent(&sandbox, "git.example.com"),
+        second.socket_path.to_str().unwrap()
+    );
+
+    sandbox.run(&["-p"]);
+    assert!(sandbox.dir.join("agency.conf").exists());
+    sandbox.run(&["clear", "--agent", &second.pid]);
+    sandbox.run(&["-p"]);
+    assert!(!sandbox.dir.join("agency.conf").exists());
+}
+
+#[test]
+fn ssh_config_include_is_removed_with_a_dead_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config(&format!(
+        "[ssh_config]\nenabled = true\npath = {:?}\n",
+        sandbox.dir.join("agency.conf")
+    ));
+    let agent = sandbox.make_agent();
+    sandbox.run(&["-y"]);
+    assert!(sandbox.dir.join("agency.conf").exists());
+
+    Command::new("kill")
+        .args(["-KILL", &agent.pid])
+        .status()
+        .unwrap();
+    sandbox.run(&["-s"]);
+    assert!(!agent.socket_path.exists());
+    assert!(!sandbox.dir.join("agency.conf").exists());
+}
@@ -0,0 +1,60 @@
+use std::fs;
+
+mod run_binary;
+use run_binary::Sandbox;
+
+#[test]
+fn stable_socket_follows_picked_agent() {
+    let sandbox = Sandbox::new();
+    let link = sandbox.dir.join("stable/agent.sock");
+    sandbox.write_config(&format!(
+        "[stable_socket]\nenabled = true\npath = {:?}\n[ez]\npolicy = \"pick\"\n",
+        link
+    ));
+    let first = sandbox.make_agent();
+
+    let output = sandbox.run(&["-y"]);
+    assert_eq!(fs::read_link(&link).unwrap(), first.socket_path);
+    assert!(output.contains(&format!("{:?}", first.socket_path)));
+
+    let second = sandbox.make_agent_with_identity();
+    let output = sandbox.run(&["-y", "--stable-socket"]);
+    assert_eq!(fs::read_link(&link).unwrap(), second.socket_path);
+    assert!(output.contains(&format!("export SSH_AUTH_SOCK={:?}", link)));
+
+    // the link isn't discovered as an agent of its own
+    assert_eq!(sandbox.run(&["-s"]).lines().count(), 2);
+
+    sandbox.run(&["-n"]);
+    assert_eq!(fs::read_link(&link).unwrap(), second.socket_path);
+    sandbox.run(&["clear", "--agent", &second.pid]);
+    sandbox.run(&["-p"]);
+    assert!(fs::symlink_metadata(&link).is_err());
+}
+
+#[test]
+fn stable_socket_in_env_is_the_current_agent() {
+    let sandbox = Sandbox::new();
+    let link = sandbox.dir.join("stable/agent.sock");
+    sandbox.write_config(&format!(
+        "[stable_socket]\nenabled = true\nexport = true\npath = {:?}\n[ez]\npolicy = \"pick\"\n",
+        link
+    ));
+    let _other = sandbox.make_agent();
+    let picked = sandbox.make_agent_with_identity();
+    let output = sandbox.run(&["-y"]);
+    assert!(output.contains(&format!("export SSH_AUTH_SOCK={:?}", link)));
+    assert_eq!(fs::read_link(&link).unwrap(), picked.socket_path);
+
+    let env = [("SSH_AUTH_SOCK", link.to_str().unwrap())];
+    let output = sandbox.run_with_env(&["-s"], &env);
+    let current: Vec<&str> = output.lines().filter(|l| l.contains(", current")).collect();
+    assert_eq!(current.len(), 1, "{output}");
+    assert!(current[0].contains(&format!("PID {}", picked.pid)));
+
+    let output = sandbox.run_with_env(&["-y", "--ez-policy", "current"], &env);
+    assert!(
+        output.contains(&format!("export SSH_AGENT_PID={}", picked.pid)),
+        "{output}"
+    );
+}
@@ -0,0 +1,76 @@
+use std::fs;
+
+use ssh_agency::agent::spawner::AgentSpawner;
+
+mod run_binary;
+use run_binary::Sandbox;
+
+#[test]
+fn setup_systemd_writes_units() {
+    let sandbox = Sandbox::new();
+    let config_home = sandbox.dir.join("config");
+    let runtime_dir = sandbox.dir.join("run");
+    let envs = [
+        ("XDG_CONFIG_HOME", config_home.to_str().unwrap()),
+        ("XDG_RUNTIME_DIR", runtime_dir.to_str().unwrap()),
+    ];
+
+    sandbox.run_with_env(&["setup", "systemd", "--no-enable"], &envs);
+    let unit =
+        fs::read_to_string(config_home.join("systemd/user/ssh-agency-agent.service")).unwrap();
+    assert!(unit.contains("rm\" -f %t/ssh-agency/systemd-agent.sock\n"));
+    assert!(unit.contains("ssh-agent\" -D -a %t/ssh-agency/systemd-agent.sock\n"));
+    assert!(unit.contains("WantedBy=default.target"));
+
+    // paths are quoted and escaped for systemd
+    let config_path = sandbox.dir.join("100% \"mine\".toml");
+    fs::copy(&sandbox.config_path, &config_path).unwrap();
+    sandbox.run_with_env(
+        &[
+            "--config",
+            config_path.to_str().unwrap(),
+            "setup",
+            "systemd",
+            "--daemon",
+            "--no-enable",
+        ],
+        &envs,
+    );
+    let unit =
+        fs::read_to_string(config_home.join("systemd/user/ssh-agency-daemon.service")).unwrap();
+    let config_dir = sandbox.dir.canonicalize().unwrap();
+    assert!(
+        unit.contains(&format!(
+            " --config \"{}/100%% \\\"mine\\\".toml\" daemon\n",
+            config_dir.display()
+        )),
+        "{unit}"
+    );
+}
+
+#[test]
+fn systemd_agent_is_discovered_and_protected() {
+    let sandbox = Sandbox::new();
+    let runtime_dir = sandbox.dir.join("run");
+    let socket_dir = runtime_dir.join("ssh-agency");
+    fs::create_dir_all(&socket_dir).unwrap();
+    let mut managed = AgentSpawner::new()
+        .socket_path(socket_dir.join("systemd-agent.sock"))
+        .spawn()
+        .unwrap();
+    let empty = sandbox.make_agent();
+    let envs = [("XDG_RUNTIME_DIR", runtime_dir.to_str().unwrap())];
+
+    let output = sandbox.run_with_env(&["-s"], &envs);
+    assert!(output.contains(&format!(
+        "PID {}: No identities at {} (Running, systemd)",
+        managed.pid,
+        managed.socket_path.display()
+    )));
+
+    sandbox.run_with_env(&["-p"], &envs);
+    let survived = managed.socket_path.exists();
+    managed.kill_and_clean_agent();
+    assert!(survived);
+    assert!(!empty.socket_path.exists());
+}
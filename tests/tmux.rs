@@ -0,0 +1,67 @@
+use std::path::Path;
+use std::process::Command;
+
+mod run_binary;
+use run_binary::Sandbox;
+
+/// Run tmux against the server in `tmpdir`, returning its output.
+fn tmux(tmpdir: &Path, args: &[&str], envs: &[(&str, &str)]) -> String {
+    let output = Command::new("tmux")
+        .args(args)
+        .env("TMUX_TMPDIR", tmpdir)
+        .env_remove("TMUX")
+        .envs(envs.iter().copied())
+        .output()
+        .expect("Unable to run tmux");
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[test]
+fn tmux_environment_follows_picked_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.write_config("[tmux]\nenabled = true\n[ez]\npolicy = \"pick\"\n");
+    let tmpdir = sandbox.dir.join("tmux");
+    std::fs::create_dir(&tmpdir).unwrap();
+    let tmpdir_str = tmpdir.to_str().unwrap();
+
+    let first = sandbox.make_agent();
+    let first_socket = first.socket_path.to_str().unwrap();
+    tmux(
+        &tmpdir,
+        &["new-session", "-d", "-s", "work"],
+        &[
+            ("SSH_AUTH_SOCK", first_socket),
+            ("SSH_AGENT_PID", &first.pid),
+        ],
+    );
+    tmux(&tmpdir, &["new-session", "-d", "-s", "play"], &[]);
+
+    let second = sandbox.make_agent_with_identity();
+    // an empty `TMUX` keeps tmux off the server the tests may be running inside of
+    let envs = [("TMUX_TMPDIR", tmpdir_str), ("TMUX", "")];
+    sandbox.run_with_env(&["-y"], &envs);
+    let expected = format!("SSH_AGENT_PID={}", second.pid);
+    assert_eq!(
+        tmux(&tmpdir, &["show-environment", "-g", "SSH_AGENT_PID"], &[]),
+        expected
+    );
+    for session in ["work", "play"] {
+        assert_eq!(
+            tmux(
+                &tmpdir,
+                &["show-environment", "-t", session, "SSH_AGENT_PID"],
+                &[]
+            ),
+            expected
+        );
+    }
+
+    // the pane started with the first agent still uses it when it's purged
+    let (_, stderr) = sandbox.run_with_stderr(&["-p"], &envs);
+    tmux(&tmpdir, &["kill-server"], &[]);
+    assert!(stderr.contains(&format!(
+        "tmux session work has 1 pane still using killed agent PID {}",
+        first.pid
+    )));
+    assert!(!stderr.contains("session play"));
+}
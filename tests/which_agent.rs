@@ -0,0 +1,31 @@
+mod run_binary;
+use run_binary::{test_identity_path, Sandbox};
+
+#[test]
+fn which_agent_by_key_file() {
+    let sandbox = Sandbox::new();
+    let _empty = sandbox.make_agent();
+    let agent = sandbox.make_agent_with_identity();
+    let public_key = test_identity_path().with_extension("pub");
+
+    let output = sandbox.run(&["which-agent", public_key.to_str().unwrap()]);
+    assert!(output.contains(&format!("PID {}", agent.pid)));
+    assert_eq!(output.lines().count(), 1);
+
+    let output = sandbox.run(&["env", "--with-key", public_key.to_str().unwrap()]);
+    assert!(output.contains(&format!("export SSH_AGENT_PID={}", agent.pid)));
+}
+
+#[test]
+fn which_agent_by_comment() {
+    let sandbox = Sandbox::new();
+    let agent = sandbox.make_agent_with_identity();
+    let comment = std::fs::read_to_string(test_identity_path().with_extension("pub")).unwrap();
+    let comment = comment.split_whitespace().nth(2).unwrap();
+
+    let output = sandbox.run(&["which-agent", "--env", comment]);
+    assert!(output.contains(&format!("export SSH_AGENT_PID={}", agent.pid)));
+
+    let error = sandbox.run_failing(&["which-agent", "no-such-comment"]);
+    assert!(error.contains("no running agent holds"));
+}